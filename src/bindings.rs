@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use sdl2::keyboard::Keycode;
+
+// Where the viewer looks for key bindings when none are given
+pub const DEFAULT_PATH: &str = "keys.cfg";
+
+// Everything a key can do in the viewer. Some only apply in one mode: the
+// spawn keys in a live run, the seek keys in a replay, so one key can carry
+// an action for each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    SpawnNorth,
+    SpawnSouth,
+    SpawnEast,
+    SpawnWest,
+    SpawnRandom,
+    Pause,
+    Step,
+    Slower,
+    Faster,
+    ToggleHud,
+    ToggleCountdown,
+    ToggleDebug,
+    ToggleOverride,
+    Follow,
+    ResetCamera,
+    Fullscreen,
+    SaveSnapshot,
+    LoadSnapshot,
+    RewindBack,
+    RewindForward,
+    Resume,
+    SeekBack,
+    SeekForward,
+    Restart,
+}
+
+impl Action {
+    pub const ALL: [Action; 25] = [
+        Action::Quit,
+        Action::SpawnNorth,
+        Action::SpawnSouth,
+        Action::SpawnEast,
+        Action::SpawnWest,
+        Action::SpawnRandom,
+        Action::Pause,
+        Action::Step,
+        Action::Slower,
+        Action::Faster,
+        Action::ToggleHud,
+        Action::ToggleCountdown,
+        Action::ToggleDebug,
+        Action::ToggleOverride,
+        Action::Follow,
+        Action::ResetCamera,
+        Action::Fullscreen,
+        Action::SaveSnapshot,
+        Action::LoadSnapshot,
+        Action::RewindBack,
+        Action::RewindForward,
+        Action::Resume,
+        Action::SeekBack,
+        Action::SeekForward,
+        Action::Restart,
+    ];
+
+    // Name used in the bindings file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::SpawnNorth => "spawn-north",
+            Action::SpawnSouth => "spawn-south",
+            Action::SpawnEast => "spawn-east",
+            Action::SpawnWest => "spawn-west",
+            Action::SpawnRandom => "spawn-random",
+            Action::Pause => "pause",
+            Action::Step => "step",
+            Action::Slower => "slower",
+            Action::Faster => "faster",
+            Action::ToggleHud => "toggle-hud",
+            Action::ToggleCountdown => "toggle-countdown",
+            Action::ToggleDebug => "toggle-debug",
+            Action::ToggleOverride => "toggle-override",
+            Action::Follow => "follow",
+            Action::ResetCamera => "reset-camera",
+            Action::Fullscreen => "fullscreen",
+            Action::SaveSnapshot => "save-snapshot",
+            Action::LoadSnapshot => "load-snapshot",
+            Action::RewindBack => "rewind-back",
+            Action::RewindForward => "rewind-forward",
+            Action::Resume => "resume",
+            Action::SeekBack => "seek-back",
+            Action::SeekForward => "seek-forward",
+            Action::Restart => "restart",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    // Keys bound to this action out of the box
+    fn default_keys(self) -> Vec<Keycode> {
+        match self {
+            Action::Quit => vec![Keycode::Escape],
+            Action::SpawnNorth => vec![Keycode::Up], // From south heading north
+            Action::SpawnSouth => vec![Keycode::Down], // From north heading south
+            Action::SpawnEast => vec![Keycode::Right], // From west heading east
+            Action::SpawnWest => vec![Keycode::Left], // From east heading west
+            Action::SpawnRandom => vec![Keycode::R],
+            Action::Pause => vec![Keycode::Space],
+            Action::Step => vec![Keycode::Period],
+            Action::Slower => vec![Keycode::Minus, Keycode::KpMinus],
+            Action::Faster => vec![Keycode::Equals, Keycode::KpPlus],
+            Action::ToggleHud => vec![Keycode::H],
+            Action::ToggleCountdown => vec![Keycode::C],
+            Action::ToggleDebug => vec![Keycode::D],
+            Action::ToggleOverride => vec![Keycode::O],
+            Action::Follow => vec![Keycode::F],
+            Action::ResetCamera => vec![Keycode::Num0, Keycode::Kp0],
+            Action::Fullscreen => vec![Keycode::F11],
+            Action::SaveSnapshot => vec![Keycode::F5],
+            Action::LoadSnapshot => vec![Keycode::F9],
+            Action::RewindBack => vec![Keycode::LeftBracket],
+            Action::RewindForward => vec![Keycode::RightBracket],
+            Action::Resume => vec![Keycode::Return],
+            Action::SeekBack => vec![Keycode::Left],
+            Action::SeekForward => vec![Keycode::Right],
+            Action::Restart => vec![Keycode::Home],
+        }
+    }
+}
+
+// Which actions each key triggers
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    keys: HashMap<Action, Vec<Keycode>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: Action::ALL
+                .into_iter()
+                .map(|a| (a, a.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    // Actions bound to `key`, in `Action::ALL` order
+    pub fn actions(&self, key: Keycode) -> Vec<Action> {
+        Action::ALL
+            .into_iter()
+            .filter(|a| self.keys[a].contains(&key))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Bindings::parse(&text, Keycode::from_name).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Defaults changed by a bindings file, one action per line with its keys
+    // after an `=`, separated by commas; `#` at the start of a line or after
+    // a space starts a comment, unless it stands alone as a key:
+    //
+    //   # arrows on the right hand, WASD on the left
+    //   spawn-north = Up, W
+    //   pause = P, #
+    //   toggle-debug =  # unbound
+    //
+    // An action listed replaces its default keys, so an empty list unbinds
+    // it. Keys are SDL key names, looked up with `key`.
+    pub fn parse(text: &str, key: impl Fn(&str) -> Option<Keycode>) -> Result<Self, String> {
+        let mut bindings = Bindings::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, keys) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected 'action = keys'", n + 1))?;
+            let action = Action::from_name(name.trim())
+                .ok_or_else(|| format!("line {}: no action '{}'", n + 1, name.trim()))?;
+            let keys = match keys.trim() {
+                "" => Vec::new(),
+                keys => keys
+                    .split(',')
+                    .map(str::trim)
+                    .map(|k| match k {
+                        "" => Err(format!("line {}: empty key name", n + 1)),
+                        k => key(k).ok_or_else(|| format!("line {}: no key '{}'", n + 1, k)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            bindings.keys.insert(action, keys);
+        }
+        Ok(bindings)
+    }
+}
+
+// `line` up to its comment. A `#` between an `=` or `,` and a `,` or the end
+// of the line is the "#" key, not a comment.
+fn strip_comment(line: &str) -> &str {
+    for (i, c) in line.char_indices() {
+        let starts_word = line[..i].ends_with(char::is_whitespace) || i == 0;
+        if c != '#' || !starts_word {
+            continue;
+        }
+        let before = line[..i].trim_end().chars().next_back();
+        let after = line[i + 1..].trim_start().chars().next();
+        let lone_key = matches!(before, Some('=' | ',')) && matches!(after, None | Some(','));
+        if !lone_key {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Option<Keycode> {
+        match name {
+            "#" => Some(Keycode::Hash),
+            "P" => Some(Keycode::P),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn hash_can_be_bound() {
+        let bindings = Bindings::parse("# comment\npause = P, # \nquit = #\n", key).unwrap();
+        assert_eq!(bindings.keys[&Action::Pause], [Keycode::P, Keycode::Hash]);
+        assert_eq!(bindings.keys[&Action::Quit], [Keycode::Hash]);
+    }
+
+    #[test]
+    fn comments_after_a_space_are_skipped() {
+        let bindings =
+            Bindings::parse("pause = P # pause\ntoggle-debug = # unbound\n", key).unwrap();
+        assert_eq!(bindings.keys[&Action::Pause], [Keycode::P]);
+        assert!(bindings.keys[&Action::ToggleDebug].is_empty());
+    }
+
+    #[test]
+    fn empty_key_names_are_refused() {
+        assert!(Bindings::parse("pause = P,\n", key).is_err());
+        assert!(Bindings::parse("pause = , P\n", key).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use sdl2::rect::{Point, Rect};
+
+pub const MIN_ZOOM: f64 = 1.0;
+pub const MAX_ZOOM: f64 = 8.0;
+const ZOOM_STEP: f64 = 1.25; // per wheel notch
+
+// Which part of the world the window shows. The world is drawn at full size
+// and this picks the rectangle of it to show in the viewport, the largest
+// area with the world's proportions that fits centred in the window. It
+// never shows anything outside the world.
+pub struct Camera {
+    world: Rect,
+    center: (f64, f64),
+    zoom: f64,
+    pub following: bool, // keep the selected vehicle in the middle
+}
+
+impl Camera {
+    pub fn new(world: Rect) -> Self {
+        let c = world.center();
+        Camera {
+            world,
+            center: (c.x as f64, c.y as f64),
+            zoom: MIN_ZOOM,
+            following: false,
+        }
+    }
+
+    // Back to the whole world
+    pub fn reset(&mut self) {
+        *self = Camera::new(self.world);
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    // The part of the world in view
+    pub fn view(&self) -> Rect {
+        let (w, h) = self.size();
+        Rect::new(
+            (self.center.0 - w / 2.0).round() as i32,
+            (self.center.1 - h / 2.0).round() as i32,
+            w.round() as u32,
+            h.round() as u32,
+        )
+    }
+
+    // Where the view goes on a window `screen` pixels big: scaled to fit
+    // with its proportions kept, centred, the rest left as bars
+    pub fn viewport(&self, screen: (u32, u32)) -> Rect {
+        let (w, h) = (self.world.width() as f64, self.world.height() as f64);
+        let scale = (screen.0 as f64 / w).min(screen.1 as f64 / h);
+        let (vw, vh) = ((w * scale).round() as u32, (h * scale).round() as u32);
+        Rect::new(
+            (screen.0 - vw.min(screen.0)) as i32 / 2,
+            (screen.1 - vh.min(screen.1)) as i32 / 2,
+            vw.max(1),
+            vh.max(1),
+        )
+    }
+
+    // World point under a window point, for a window `screen` pixels big
+    pub fn to_world(&self, p: Point, screen: (u32, u32)) -> Point {
+        // Measured on the rounded view, which is what's on screen
+        let (view, port) = (self.view(), self.viewport(screen));
+        let along = |p: i32, origin: i32, size: u32, port: u32| {
+            origin + ((p as i64) * size as i64 / port as i64) as i32
+        };
+        Point::new(
+            along(p.x - port.x(), view.x(), view.width(), port.width()),
+            along(p.y - port.y(), view.y(), view.height(), port.height()),
+        )
+    }
+
+    // Zoom in (positive `notches`) or out, keeping the world point under the
+    // window point `anchor` where it is
+    pub fn zoom_at(&mut self, notches: i32, anchor: Point, screen: (u32, u32)) {
+        let before = self.unproject(anchor, screen);
+        self.zoom = (self.zoom * ZOOM_STEP.powi(notches)).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.clamp();
+        let after = self.unproject(anchor, screen);
+        self.center.0 += before.0 - after.0;
+        self.center.1 += before.1 - after.1;
+        self.clamp();
+    }
+
+    // Drag the world by a mouse movement of (dx, dy) window pixels
+    pub fn pan(&mut self, dx: i32, dy: i32, screen: (u32, u32)) {
+        let ((w, h), port) = (self.size(), self.viewport(screen));
+        self.center.0 -= dx as f64 * w / port.width() as f64;
+        self.center.1 -= dy as f64 * h / port.height() as f64;
+        self.following = false;
+        self.clamp();
+    }
+
+    // Centre the view on `p`, as far as the world edges allow
+    pub fn look_at(&mut self, p: Point) {
+        self.center = (p.x as f64, p.y as f64);
+        self.clamp();
+    }
+
+    fn size(&self) -> (f64, f64) {
+        (
+            self.world.width() as f64 / self.zoom,
+            self.world.height() as f64 / self.zoom,
+        )
+    }
+
+    fn unproject(&self, p: Point, screen: (u32, u32)) -> (f64, f64) {
+        let ((w, h), port) = (self.size(), self.viewport(screen));
+        let (x, y) = ((p.x - port.x()) as f64, (p.y - port.y()) as f64);
+        (
+            self.center.0 - w / 2.0 + x * w / port.width() as f64,
+            self.center.1 - h / 2.0 + y * h / port.height() as f64,
+        )
+    }
+
+    // Keep the view inside the world
+    fn clamp(&mut self) {
+        let (w, h) = self.size();
+        let (left, top) = (self.world.x() as f64, self.world.y() as f64);
+        let (right, bottom) = (self.world.right() as f64, self.world.bottom() as f64);
+        self.center.0 = self.center.0.clamp(left + w / 2.0, right - w / 2.0);
+        self.center.1 = self.center.1.clamp(top + h / 2.0, bottom - h / 2.0);
+    }
+}
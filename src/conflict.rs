@@ -0,0 +1,512 @@
+use sdl2::rect::{Point, Rect};
+
+use crate::intersection::{BOX_MAX, BOX_MIN, CENTER, LANE_OFFSET};
+use crate::vehicle::{Direction, Turn, Vehicle, turned};
+
+// How far (along its path) a vehicle's centre must stay from a conflict point
+// it does not own: half a vehicle length plus half a lane width plus margin
+const CLEARANCE: i32 = 35;
+
+pub const MOVEMENT_COUNT: usize = 12;
+
+// Length of exit lane past the box that must be free of stopped vehicles
+// before anyone enters: one vehicle plus its following gap
+const EXIT_ROOM: i32 = 40 + 50;
+
+// A movement through the box: the heading a vehicle arrives with plus its turn.
+// `approach` uses the same convention as `Direction` (North = northbound,
+// i.e. arriving from the south).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Movement {
+    pub approach: Direction,
+    pub turn: Turn,
+}
+
+impl Movement {
+    // All twelve movements, in a fixed order used for indexing
+    pub fn all() -> Vec<Movement> {
+        let mut all = Vec::with_capacity(MOVEMENT_COUNT);
+        for approach in [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ] {
+            for turn in [Turn::Straight, Turn::Right, Turn::Left] {
+                all.push(Movement { approach, turn });
+            }
+        }
+        all
+    }
+
+    pub fn index(&self) -> usize {
+        let approach = match self.approach {
+            Direction::North => 0,
+            Direction::South => 1,
+            Direction::East => 2,
+            Direction::West => 3,
+        };
+        let turn = match self.turn {
+            Turn::Straight => 0,
+            Turn::Right => 1,
+            Turn::Left => 2,
+        };
+        approach * 3 + turn
+    }
+
+    pub fn exit(&self) -> Direction {
+        turned(self.approach, self.turn)
+    }
+
+    // Centre-line path through the box as a polyline of axis-aligned segments
+    pub fn path(&self) -> Vec<Point> {
+        let exit = self.exit();
+        let mut points = vec![entry_point(self.approach)];
+        if self.turn != Turn::Straight {
+            let corner = if is_vertical(self.approach) {
+                Point::new(lane_center(self.approach), lane_center(exit))
+            } else {
+                Point::new(lane_center(exit), lane_center(self.approach))
+            };
+            points.push(corner);
+        }
+        points.push(exit_point(exit));
+        points
+    }
+
+    // Stretch of exit lane just past the box this movement needs room on
+    pub fn exit_zone(&self) -> Rect {
+        let exit = self.exit();
+        let c = lane_center(exit);
+        let room = EXIT_ROOM as u32;
+        match exit {
+            Direction::North => Rect::new(c - 10, BOX_MIN - EXIT_ROOM, 20, room),
+            Direction::South => Rect::new(c - 10, BOX_MAX, 20, room),
+            Direction::East => Rect::new(BOX_MAX, c - 10, room, 20),
+            Direction::West => Rect::new(BOX_MIN - EXIT_ROOM, c - 10, room, 20),
+        }
+    }
+
+    // Distance travelled along the path by a vehicle whose centre is at `p`.
+    // Negative before the box entry, beyond the path length after the exit.
+    pub fn progress(&self, p: Point) -> i32 {
+        let path = self.path();
+        let last = path.len() - 2;
+        let mut travelled = 0;
+        let mut best: Option<(i32, i32)> = None; // (off-path distance, progress)
+
+        for (k, seg) in path.windows(2).enumerate() {
+            let (a, b) = (seg[0], seg[1]);
+            let len = manhattan(a, b);
+            // Signed offset along the segment and distance across it
+            let (along, across) = if a.x() == b.x() {
+                (
+                    (p.y() - a.y()) * (b.y() - a.y()).signum(),
+                    (p.x() - a.x()).abs(),
+                )
+            } else {
+                (
+                    (p.x() - a.x()) * (b.x() - a.x()).signum(),
+                    (p.y() - a.y()).abs(),
+                )
+            };
+            let lo = if k == 0 { i32::MIN } else { 0 };
+            let hi = if k == last { i32::MAX } else { len };
+            let clamped = along.clamp(lo, hi);
+            let off = across + (along - clamped).abs();
+            if best.is_none_or(|(d, _)| off < d) {
+                best = Some((off, travelled + clamped));
+            }
+            travelled += len;
+        }
+        best.map(|(_, s)| s).unwrap_or(0)
+    }
+
+    // Points where this movement's path meets another's inside the box.
+    // Movements from the same approach share a queue, not a conflict point.
+    pub fn conflict_points(&self, other: &Movement) -> Vec<Point> {
+        if self.approach == other.approach {
+            return Vec::new();
+        }
+        let (pa, pb) = (self.path(), other.path());
+        let mut points: Vec<Point> = Vec::new();
+        for sa in pa.windows(2) {
+            for sb in pb.windows(2) {
+                for p in segment_intersections(sa[0], sa[1], sb[0], sb[1]) {
+                    if !points.contains(&p) {
+                        points.push(p);
+                    }
+                }
+            }
+        }
+        // Where paths merge they overlap over a stretch; keep only the point
+        // where they first meet, which is the one nearest both starts
+        if let Some(merge) = merge_point(self, other, &points) {
+            points.retain(|p| !on_shared_exit(self, other, *p) || *p == merge);
+        }
+        points
+    }
+
+    pub fn conflicts_with(&self, other: &Movement) -> bool {
+        !self.conflict_points(other).is_empty()
+    }
+
+    // Lower goes first when two movements otherwise tie for a conflict point
+    fn rank(&self) -> u8 {
+        match self.turn {
+            Turn::Straight => 0,
+            Turn::Right => 1,
+            Turn::Left => 2,
+        }
+    }
+}
+
+// Which movements conflict with which, derived from the turn paths above
+#[derive(Clone, Debug)]
+pub struct ConflictMatrix {
+    conflicts: [[bool; MOVEMENT_COUNT]; MOVEMENT_COUNT],
+}
+
+impl ConflictMatrix {
+    pub fn from_geometry() -> Self {
+        let mut conflicts = [[false; MOVEMENT_COUNT]; MOVEMENT_COUNT];
+        for a in Movement::all() {
+            for b in Movement::all() {
+                conflicts[a.index()][b.index()] = a.conflicts_with(&b);
+            }
+        }
+        ConflictMatrix { conflicts }
+    }
+
+    pub fn conflicts(&self, a: Movement, b: Movement) -> bool {
+        self.conflicts[a.index()][b.index()]
+    }
+
+    // True if every movement in the set can run green together
+    pub fn compatible(&self, movements: &[Movement]) -> bool {
+        movements
+            .iter()
+            .enumerate()
+            .all(|(i, a)| movements[i + 1..].iter().all(|b| !self.conflicts(*a, *b)))
+    }
+}
+
+pub fn in_box(rect: Rect) -> bool {
+    let size = (BOX_MAX - BOX_MIN) as u32;
+    rect.has_intersection(Rect::new(BOX_MIN, BOX_MIN, size, size))
+}
+
+// Another stopped vehicle (index into `others`) sitting on the stretch of
+// exit lane `vehicle` would need, so entering now would leave it stuck in the
+// box
+pub fn exit_blocker(vehicle: &Vehicle, others: &[Vehicle]) -> Option<usize> {
+    let zone = vehicle.movement().exit_zone();
+    others
+        .iter()
+        .position(|o| o.id() != vehicle.id() && o.stalled > 0 && o.rect().has_intersection(zone))
+}
+
+fn is_vertical(direction: Direction) -> bool {
+    matches!(direction, Direction::North | Direction::South)
+}
+
+// x of a vertical lane's centre line, y of a horizontal one's
+fn lane_center(direction: Direction) -> i32 {
+    match direction {
+        Direction::North | Direction::East => CENTER + LANE_OFFSET,
+        Direction::South | Direction::West => CENTER - LANE_OFFSET,
+    }
+}
+
+fn entry_point(direction: Direction) -> Point {
+    let lane = lane_center(direction);
+    match direction {
+        Direction::North => Point::new(lane, BOX_MAX),
+        Direction::South => Point::new(lane, BOX_MIN),
+        Direction::East => Point::new(BOX_MIN, lane),
+        Direction::West => Point::new(BOX_MAX, lane),
+    }
+}
+
+fn exit_point(direction: Direction) -> Point {
+    let lane = lane_center(direction);
+    match direction {
+        Direction::North => Point::new(lane, BOX_MIN),
+        Direction::South => Point::new(lane, BOX_MAX),
+        Direction::East => Point::new(BOX_MAX, lane),
+        Direction::West => Point::new(BOX_MIN, lane),
+    }
+}
+
+fn manhattan(a: Point, b: Point) -> i32 {
+    (a.x() - b.x()).abs() + (a.y() - b.y()).abs()
+}
+
+fn between(v: i32, a: i32, b: i32) -> bool {
+    v >= a.min(b) && v <= a.max(b)
+}
+
+// Intersections of two axis-aligned segments. Collinear overlaps yield the
+// endpoints of the shared stretch.
+fn segment_intersections(a1: Point, a2: Point, b1: Point, b2: Point) -> Vec<Point> {
+    let a_vertical = a1.x() == a2.x();
+    let b_vertical = b1.x() == b2.x();
+    match (a_vertical, b_vertical) {
+        (true, true) if a1.x() == b1.x() => overlap(a1.y(), a2.y(), b1.y(), b2.y())
+            .into_iter()
+            .map(|y| Point::new(a1.x(), y))
+            .collect(),
+        (false, false) if a1.y() == b1.y() => overlap(a1.x(), a2.x(), b1.x(), b2.x())
+            .into_iter()
+            .map(|x| Point::new(x, a1.y()))
+            .collect(),
+        (true, false) if between(a1.x(), b1.x(), b2.x()) && between(b1.y(), a1.y(), a2.y()) => {
+            vec![Point::new(a1.x(), b1.y())]
+        }
+        (false, true) if between(b1.x(), a1.x(), a2.x()) && between(a1.y(), b1.y(), b2.y()) => {
+            vec![Point::new(b1.x(), a1.y())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn overlap(a1: i32, a2: i32, b1: i32, b2: i32) -> Vec<i32> {
+    let lo = a1.min(a2).max(b1.min(b2));
+    let hi = a1.max(a2).min(b1.max(b2));
+    match lo.cmp(&hi) {
+        std::cmp::Ordering::Less => vec![lo, hi],
+        std::cmp::Ordering::Equal => vec![lo],
+        std::cmp::Ordering::Greater => Vec::new(),
+    }
+}
+
+fn on_shared_exit(a: &Movement, b: &Movement, p: Point) -> bool {
+    a.exit() == b.exit()
+        && if is_vertical(a.exit()) {
+            p.x() == lane_center(a.exit())
+        } else {
+            p.y() == lane_center(a.exit())
+        }
+}
+
+fn merge_point(a: &Movement, b: &Movement, points: &[Point]) -> Option<Point> {
+    points
+        .iter()
+        .filter(|p| on_shared_exit(a, b, **p))
+        .min_by_key(|p| a.progress(**p) + b.progress(**p))
+        .copied()
+}
+
+// Progress of a vehicle along its own movement path
+fn progress_of(vehicle: &Vehicle) -> i32 {
+    vehicle.movement().progress(vehicle.rect().center())
+}
+
+// Conflict points between two vehicles that neither has cleared yet, as
+// (distance along a's path, distance along b's path)
+fn live_conflicts(a: &Vehicle, b: &Vehicle) -> Vec<(i32, i32)> {
+    let (ma, mb) = (a.movement(), b.movement());
+    let (pa, pb) = (progress_of(a), progress_of(b));
+    ma.conflict_points(&mb)
+        .into_iter()
+        .map(|p| (ma.progress(p), mb.progress(p)))
+        .filter(|(sa, sb)| pa <= sa + CLEARANCE && pb <= sb + CLEARANCE)
+        .collect()
+}
+
+// Decide which vehicles may take their tentative step this tick.
+//
+// `moving` comes in as the result of the same-lane following check. Vehicles
+// outside the box are held at the line while a conflicting vehicle inside has
+// not cleared; vehicles inside give way at conflict points to whoever is
+// nearer; and a final pass guarantees no two bodies ever overlap. Whoever
+// holds a vehicle back is recorded in `blocked_by`.
+pub fn resolve(
+    current: &[Vehicle],
+    tentatives: &[Vehicle],
+    mut moving: Vec<bool>,
+    blocked_by: &mut [Option<usize>],
+) -> Vec<bool> {
+    let n = current.len();
+
+    for i in 0..n {
+        if !moving[i] {
+            continue;
+        }
+        let me = &current[i];
+        let entering = tentatives[i].in_intersection && !me.in_intersection;
+        let my_progress = progress_of(&tentatives[i]);
+
+        for j in 0..n {
+            if i == j {
+                continue;
+            }
+            let other = &current[j];
+            let other_entering =
+                tentatives[j].in_intersection && !other.in_intersection && moving[j];
+            if !other.in_intersection && !other_entering {
+                continue;
+            }
+            let conflicts = live_conflicts(me, other);
+            if conflicts.is_empty() {
+                continue;
+            }
+
+            let yields = if entering {
+                // Never enter across a conflicting vehicle already in the box;
+                // between two simultaneous entrants the higher-ranked goes
+                other.in_intersection || (me.movement().rank(), i) > (other.movement().rank(), j)
+            } else if me.in_intersection && other.in_intersection {
+                // Inside the box the vehicle nearer the conflict point goes
+                // first; it must not advance into a point it doesn't own
+                let mine = conflicts.iter().map(|(sa, _)| sa - progress_of(me)).min();
+                let theirs = conflicts
+                    .iter()
+                    .map(|(_, sb)| sb - progress_of(other))
+                    .min();
+                let key = |d: Option<i32>, m: &Vehicle, k: usize| (d, m.movement().rank(), k);
+                key(mine, me, i) > key(theirs, other, j)
+                    && conflicts.iter().any(|(sa, _)| {
+                        my_progress > sa - CLEARANCE && progress_of(me) <= sa - CLEARANCE
+                    })
+            } else {
+                false
+            };
+
+            if yields {
+                moving[i] = false;
+                blocked_by[i] = Some(j);
+                break;
+            }
+        }
+    }
+
+    // Final safety pass: nobody ends a move overlapping another vehicle.
+    // Vehicles in the box take precedence, then the longest-present one; the
+    // later of two is held, and the earlier only if it would drive further
+    // into a vehicle that is staying put.
+    let priority = |k: usize| (!current[k].in_intersection, k);
+    loop {
+        let mut changed = false;
+        for i in 0..n {
+            if !moving[i] {
+                continue;
+            }
+            for j in 0..n {
+                if i == j {
+                    continue;
+                }
+                let other = if moving[j] {
+                    tentatives[j].rect()
+                } else {
+                    current[j].rect()
+                };
+                let overlap = overlap_area(tentatives[i].rect(), other);
+                if overlap == 0 {
+                    continue;
+                }
+                let before = overlap_area(current[i].rect(), current[j].rect());
+                let (loser, winner) = if priority(i) > priority(j) {
+                    (i, j)
+                } else if moving[j] {
+                    (j, i)
+                } else if overlap > before {
+                    (i, j)
+                } else {
+                    continue;
+                };
+                moving[loser] = false;
+                blocked_by[loser] = Some(winner);
+                changed = true;
+                if loser == i {
+                    break;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    moving
+}
+
+// Area two rects share
+fn overlap_area(a: Rect, b: Rect) -> u32 {
+    a.intersection(b).map_or(0, |r| r.width() * r.height())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::traffic_light::LightState;
+    use std::path::Path;
+
+    // A vehicle already in the box, past its turn, at `rect`
+    fn in_box(id: u32, direction: Direction, rect: Rect) -> Vehicle {
+        let mut v = Vehicle::with_turn(id, direction, Turn::Straight);
+        v.rect = rect;
+        v.in_intersection = true;
+        v.has_turned = true;
+        v
+    }
+
+    // How much the two vehicles overlap before and after one `resolve`, all
+    // lights green
+    fn step(current: Vec<Vehicle>) -> (u32, u32) {
+        let tentatives: Vec<Vehicle> = current
+            .iter()
+            .map(|v| {
+                let mut t = v.clone();
+                t.update(LightState::Green);
+                t
+            })
+            .collect();
+        let mut blocked_by = vec![None; current.len()];
+        let moving = resolve(&current, &tentatives, vec![true; 2], &mut blocked_by);
+        let after: Vec<Rect> = (0..2)
+            .map(|k| match moving[k] {
+                true => tentatives[k].rect(),
+                false => current[k].rect(),
+            })
+            .collect();
+        (
+            overlap_area(current[0].rect(), current[1].rect()),
+            overlap_area(after[0], after[1]),
+        )
+    }
+
+    #[test]
+    fn crossing_vehicles_never_start_to_overlap() {
+        // Northbound and eastbound straight, each about to reach the other's lane
+        let north = in_box(1, Direction::North, Rect::new(415, 437, 20, 40));
+        let east = in_box(2, Direction::East, Rect::new(374, 415, 40, 20));
+        assert_eq!(step(vec![north, east]), (0, 0));
+    }
+
+    #[test]
+    fn overlapping_crossing_vehicles_never_overlap_more() {
+        let north = in_box(1, Direction::North, Rect::new(415, 425, 20, 40));
+        let east = in_box(2, Direction::East, Rect::new(390, 415, 40, 20));
+        let (before, after) = step(vec![north, east]);
+        assert!(before > 0);
+        assert!(after <= before, "overlap grew from {} to {}", before, after);
+    }
+
+    #[test]
+    fn a_long_busy_run_never_overlaps_two_vehicles() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/rush-hour.toml");
+        let scenario = Scenario::load(&path).unwrap();
+        let mut sim = scenario.simulation(11).unwrap();
+        for _ in 0..6000 {
+            sim.step();
+            let rects: Vec<Rect> = sim.vehicles.iter().map(|v| v.rect()).collect();
+            for (k, a) in rects.iter().enumerate() {
+                for b in &rects[k + 1..] {
+                    assert_eq!(overlap_area(*a, *b), 0, "frame {}", sim.frame());
+                }
+            }
+        }
+        assert!(sim.stats.overall().throughput > 20);
+    }
+}
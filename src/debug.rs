@@ -0,0 +1,109 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::font::{self, GLYPH_HEIGHT};
+use crate::intersection::{BOX_MAX, BOX_MIN};
+use crate::simulation::Simulation;
+use crate::stats::APPROACHES;
+use crate::vehicle::{self, Hold, Turn};
+
+const SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = ((GLYPH_HEIGHT + 3) * SCALE) as i32;
+
+const BOX: Color = Color::RGB(255, 255, 255);
+const STOP_ZONE: Color = Color::RGB(255, 60, 60);
+const LOOK_AHEAD: Color = Color::RGB(140, 140, 140);
+const MOVING: Color = Color::RGB(0, 220, 0);
+
+// Outline colour of a vehicle by what held it last frame
+fn hold_color(hold: Option<Hold>) -> Color {
+    match hold {
+        None => MOVING,
+        Some(Hold::Light) => Color::RGB(255, 60, 60),
+        Some(Hold::Following) => Color::RGB(255, 150, 0),
+        Some(Hold::Exit) => Color::RGB(170, 90, 255),
+        Some(Hold::Conflict) => Color::RGB(255, 0, 255),
+    }
+}
+
+fn turn_color(turn: Turn) -> Color {
+    match turn {
+        Turn::Straight => Color::CYAN,
+        Turn::Right => Color::YELLOW,
+        Turn::Left => Color::RGB(80, 120, 255),
+    }
+}
+
+// Geometry and decision state on top of the scene: the box, each approach's
+// red-light stop zone, the turn points, and per vehicle its rect (coloured
+// by why it's held), its safe-distance look-ahead and a line to whoever is
+// blocking it. The colour key is drawn separately by `draw_legend`, since
+// it belongs on the window rather than in the world.
+pub fn draw(canvas: &mut Canvas<Window>, sim: &Simulation) {
+    let size = (BOX_MAX - BOX_MIN) as u32;
+    canvas.set_draw_color(BOX);
+    let _ = canvas.draw_rect(Rect::new(BOX_MIN, BOX_MIN, size, size));
+
+    canvas.set_draw_color(STOP_ZONE);
+    for approach in APPROACHES {
+        let _ = canvas.draw_rect(vehicle::stop_zone(approach));
+    }
+
+    for approach in APPROACHES {
+        for turn in [Turn::Straight, Turn::Right, Turn::Left] {
+            let p = vehicle::turn_point(approach, turn);
+            canvas.set_draw_color(turn_color(turn));
+            let _ = canvas.draw_line(p.offset(-3, 0), p.offset(3, 0));
+            let _ = canvas.draw_line(p.offset(0, -3), p.offset(0, 3));
+        }
+    }
+
+    for v in &sim.vehicles {
+        canvas.set_draw_color(LOOK_AHEAD);
+        let _ = canvas.draw_rect(v.look_ahead(sim.safe_distance() as u32));
+    }
+    for v in &sim.vehicles {
+        let color = hold_color(v.held);
+        canvas.set_draw_color(color);
+        let _ = canvas.draw_rect(v.rect());
+        let inner = Rect::new(
+            v.rect().x() + 1,
+            v.rect().y() + 1,
+            v.rect().width().saturating_sub(2),
+            v.rect().height().saturating_sub(2),
+        );
+        let _ = canvas.draw_rect(inner);
+        if let Some(other) = v
+            .blocked_by
+            .and_then(|id| sim.vehicles.iter().find(|o| o.id() == id))
+        {
+            let _ = canvas.draw_line(v.rect().center(), other.rect().center());
+        }
+    }
+}
+
+// Key to the colours in the bottom-left corner
+pub fn draw_legend(canvas: &mut Canvas<Window>) {
+    let entries = [
+        ("Moving", MOVING),
+        ("Red light", hold_color(Some(Hold::Light))),
+        ("Following", hold_color(Some(Hold::Following))),
+        ("Exit blocked", hold_color(Some(Hold::Exit))),
+        ("Conflict", hold_color(Some(Hold::Conflict))),
+        ("Stop zone", STOP_ZONE),
+        ("Look-ahead", LOOK_AHEAD),
+    ];
+    let (_, height) = canvas.output_size().unwrap_or((800, 800));
+    let (x, bottom) = (6, height as i32 - 6);
+    let top = bottom - entries.len() as i32 * LINE_HEIGHT - 6;
+    canvas.set_draw_color(Color::RGB(30, 30, 30));
+    let _ = canvas.fill_rect(Rect::new(x, top, 180, (bottom - top) as u32));
+    for (i, (label, color)) in entries.iter().enumerate() {
+        let y = top + 6 + i as i32 * LINE_HEIGHT;
+        canvas.set_draw_color(*color);
+        let _ = canvas.fill_rect(Rect::new(x + 6, y, 12, 12));
+        font::draw_text(canvas, x + 24, y, label, SCALE, Color::WHITE);
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Write;
+
+use crate::conflict::{MOVEMENT_COUNT, Movement};
+use crate::export::{direction_name, turn_name};
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::stats::SignalChange;
+use crate::traffic_light::LightState;
+
+// Layout of the timing diagram, in SVG user units
+const LABEL_WIDTH: f64 = 110.0;
+const ROW_HEIGHT: f64 = 18.0;
+const BAR_HEIGHT: f64 = 12.0;
+const TOP: f64 = 20.0;
+const AXIS_HEIGHT: f64 = 24.0;
+// Widest the time axis gets, and the most it spreads one second
+const MAX_PLOT_WIDTH: f64 = 2400.0;
+const MAX_SECOND_WIDTH: f64 = 8.0;
+// Gridline spacings to pick from, in seconds
+const TICKS: [u32; 13] = [1, 2, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600];
+
+// Signal timing diagram of the frames before `end_frame`: one row per
+// movement, coloured by what its head showed, against time in seconds.
+// Whole runs are squeezed to fit `MAX_PLOT_WIDTH`; it's a vector image, so
+// zooming in brings back the detail.
+pub fn timing_svg(log: &[SignalChange], end_frame: i32) -> String {
+    let frames = (end_frame - 1).max(1);
+    let seconds = frames as f64 / FRAMES_PER_SECOND as f64;
+    let scale = (MAX_PLOT_WIDTH / seconds).min(MAX_SECOND_WIDTH);
+    let x = |frame: i32| LABEL_WIDTH + (frame - 1) as f64 / FRAMES_PER_SECOND as f64 * scale;
+    let width = x(end_frame) + 10.0;
+    let height = TOP + ROW_HEIGHT * MOVEMENT_COUNT as f64 + AXIS_HEIGHT;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"11\">",
+        width, height
+    );
+    let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+
+    // Gridlines with their times, at least 60 units apart
+    let step = TICKS
+        .into_iter()
+        .find(|t| *t as f64 * scale >= 60.0)
+        .unwrap_or(3600);
+    let bottom = TOP + ROW_HEIGHT * MOVEMENT_COUNT as f64;
+    for t in (0..=seconds as u32).step_by(step as usize) {
+        let gx = x((t * FRAMES_PER_SECOND) as i32 + 1);
+        let _ = writeln!(
+            out,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>",
+            gx,
+            TOP,
+            gx,
+            bottom + 4.0
+        );
+        let _ = writeln!(
+            out,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}:{:02}</text>",
+            gx,
+            bottom + 16.0,
+            t / 60,
+            t % 60
+        );
+    }
+
+    // Each movement's states, a bar from each change to the next
+    for (row, m) in Movement::all().into_iter().enumerate() {
+        let y = TOP + ROW_HEIGHT * row as f64;
+        let _ = writeln!(
+            out,
+            "<text x=\"4\" y=\"{:.1}\">{} {}</text>",
+            y + BAR_HEIGHT - 2.0,
+            direction_name(m.approach),
+            turn_name(m.turn)
+        );
+        let changes: Vec<&SignalChange> = log.iter().filter(|c| c.movement == m).collect();
+        for (i, change) in changes.iter().enumerate() {
+            let until = changes.get(i + 1).map_or(end_frame, |next| next.frame);
+            if until <= change.frame {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                "<rect x=\"{:.2}\" y=\"{:.1}\" width=\"{:.2}\" height=\"{:.1}\" fill=\"{}\"/>",
+                x(change.frame),
+                y,
+                x(until) - x(change.frame),
+                BAR_HEIGHT,
+                color(change.state)
+            );
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+fn color(state: LightState) -> &'static str {
+    match state {
+        LightState::Red => "#c80000",
+        LightState::Yellow => "#ffaa00",
+        LightState::Green => "#00a000",
+    }
+}
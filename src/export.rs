@@ -0,0 +1,423 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::conflict::{MOVEMENT_COUNT, Movement};
+use crate::diagram;
+use crate::los::LevelOfService;
+use crate::simulation::{FRAMES_PER_SECOND, Simulation};
+use crate::stats::{FlowSummary, SignalChange, Statistics, VehicleRecord};
+use crate::traffic_light::LightState;
+use crate::vehicle::{Direction, Hold, Turn};
+use crate::webster;
+
+// Bumped whenever a column or field is renamed, removed or changes meaning.
+// Adding new columns/fields at the end does not bump it.
+pub const SCHEMA_VERSION: u32 = 2;
+
+// 15 minutes, the usual turning-movement-count bin
+pub const DEFAULT_INTERVAL: u32 = 15 * 60 * FRAMES_PER_SECOND;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    // Signal timing diagram
+    Svg,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "svg" => Ok(ExportFormat::Svg),
+            other => Err(format!(
+                "unknown export format '{}' (csv, json or svg)",
+                other
+            )),
+        }
+    }
+}
+
+// Parse a comma-separated list such as "csv,json"
+pub fn parse_formats(list: &str) -> Result<Vec<ExportFormat>, String> {
+    list.split(',').map(str::parse).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    pub dir: PathBuf,
+    pub formats: Vec<ExportFormat>,
+    pub interval: u32,   // frames per aggregation bin
+    pub saturation: f64, // vehicles per hour of green, for the level of service
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            dir: PathBuf::from("results"),
+            formats: vec![ExportFormat::Csv],
+            interval: DEFAULT_INTERVAL,
+            saturation: webster::SATURATION,
+        }
+    }
+}
+
+// Vehicles of one movement leaving during one aggregation bin
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntervalCount {
+    pub start_frame: i32,
+    pub end_frame: i32,
+    pub movement: Movement,
+    pub count: u32,
+    pub mean_delay: f64, // seconds
+}
+
+// Turning movement counts: every movement for every bin up to `end_frame`,
+// including bins where nothing moved
+pub fn interval_counts(stats: &Statistics, end_frame: i32, interval: u32) -> Vec<IntervalCount> {
+    let interval = interval.max(1) as i32;
+    let bins = ((end_frame - 1).max(0) / interval + 1) as usize;
+    let mut counts = vec![[(0u32, 0u64); MOVEMENT_COUNT]; bins];
+    for r in &stats.records {
+        if let Some(exit) = r.exit_frame {
+            let bin = (((exit - 1).max(0) / interval) as usize).min(bins - 1);
+            let cell = &mut counts[bin][r.movement.index()];
+            cell.0 += 1;
+            cell.1 += r.delay_frames as u64;
+        }
+    }
+    let mut out = Vec::new();
+    for (bin, cells) in counts.iter().enumerate() {
+        for m in Movement::all() {
+            let (count, delay) = cells[m.index()];
+            let mean_delay = if count == 0 {
+                0.0
+            } else {
+                delay as f64 / count as f64 / FRAMES_PER_SECOND as f64
+            };
+            out.push(IntervalCount {
+                start_frame: bin as i32 * interval + 1,
+                end_frame: (bin as i32 + 1) * interval,
+                movement: m,
+                count,
+                mean_delay,
+            });
+        }
+    }
+    out
+}
+
+// Write the requested formats into `options.dir`, returning the files written
+pub fn export(
+    stats: &Statistics,
+    end_frame: i32,
+    options: &ExportOptions,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(&options.dir)?;
+    let intervals = interval_counts(stats, end_frame, options.interval);
+    let los = LevelOfService::measure(stats, end_frame, options.saturation);
+    let mut written = Vec::new();
+    for format in &options.formats {
+        match format {
+            ExportFormat::Csv => {
+                written.push(write(
+                    &options.dir,
+                    "vehicles.csv",
+                    vehicles_csv(&stats.records),
+                )?);
+                written.push(write(
+                    &options.dir,
+                    "intervals.csv",
+                    intervals_csv(&intervals),
+                )?);
+                written.push(write(
+                    &options.dir,
+                    "signals.csv",
+                    signals_csv(&stats.signal_log),
+                )?);
+                written.push(write(&options.dir, "los.csv", los.csv())?);
+            }
+            ExportFormat::Json => {
+                let json = results_json(stats, &intervals, &los);
+                written.push(write(&options.dir, "results.json", json)?);
+            }
+            ExportFormat::Svg => {
+                let svg = diagram::timing_svg(&stats.signal_log, end_frame);
+                written.push(write(&options.dir, "timing.svg", svg)?);
+            }
+        }
+    }
+    Ok(written)
+}
+
+fn write(dir: &Path, name: &str, contents: String) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+pub fn direction_name(d: Direction) -> &'static str {
+    match d {
+        Direction::North => "north",
+        Direction::South => "south",
+        Direction::East => "east",
+        Direction::West => "west",
+    }
+}
+
+pub fn turn_name(t: Turn) -> &'static str {
+    match t {
+        Turn::Straight => "straight",
+        Turn::Right => "right",
+        Turn::Left => "left",
+    }
+}
+
+// Inverse of `direction_name`
+pub fn direction_from_name(name: &str) -> Option<Direction> {
+    match name {
+        "north" => Some(Direction::North),
+        "south" => Some(Direction::South),
+        "east" => Some(Direction::East),
+        "west" => Some(Direction::West),
+        _ => None,
+    }
+}
+
+// Inverse of `turn_name`
+pub fn turn_from_name(name: &str) -> Option<Turn> {
+    match name {
+        "straight" => Some(Turn::Straight),
+        "right" => Some(Turn::Right),
+        "left" => Some(Turn::Left),
+        _ => None,
+    }
+}
+
+pub fn hold_name(h: Hold) -> &'static str {
+    match h {
+        Hold::Light => "light",
+        Hold::Following => "following",
+        Hold::Exit => "exit",
+        Hold::Conflict => "conflict",
+    }
+}
+
+// Inverse of `hold_name`
+pub fn hold_from_name(name: &str) -> Option<Hold> {
+    match name {
+        "light" => Some(Hold::Light),
+        "following" => Some(Hold::Following),
+        "exit" => Some(Hold::Exit),
+        "conflict" => Some(Hold::Conflict),
+        _ => None,
+    }
+}
+
+pub fn light_state_name(state: LightState) -> &'static str {
+    match state {
+        LightState::Red => "red",
+        LightState::Yellow => "yellow",
+        LightState::Green => "green",
+    }
+}
+
+// Inverse of `light_state_name`
+pub fn light_state_from_name(name: &str) -> Option<LightState> {
+    match name {
+        "red" => Some(LightState::Red),
+        "yellow" => Some(LightState::Yellow),
+        "green" => Some(LightState::Green),
+        _ => None,
+    }
+}
+
+fn seconds(frames: i64) -> String {
+    format!("{:.3}", frames as f64 / FRAMES_PER_SECOND as f64)
+}
+
+fn vehicles_csv(records: &[VehicleRecord]) -> String {
+    let mut out = String::from(
+        "id,approach,turn,spawn_frame,exit_frame,spawn_s,exit_s,stopped_s,delay_s,removed\n",
+    );
+    for r in records {
+        let exit_frame = r.exit_frame.map(|f| f.to_string()).unwrap_or_default();
+        let exit_s = r.exit_frame.map(|f| seconds(f as i64)).unwrap_or_default();
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            r.id,
+            direction_name(r.movement.approach),
+            turn_name(r.movement.turn),
+            r.spawn_frame,
+            exit_frame,
+            seconds(r.spawn_frame as i64),
+            exit_s,
+            seconds(r.stopped_frames as i64),
+            seconds(r.delay_frames as i64),
+            r.removed
+        ));
+    }
+    out
+}
+
+fn intervals_csv(intervals: &[IntervalCount]) -> String {
+    let mut out =
+        String::from("start_frame,end_frame,start_s,end_s,approach,turn,count,mean_delay_s\n");
+    for i in intervals {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{:.3}\n",
+            i.start_frame,
+            i.end_frame,
+            seconds(i.start_frame as i64 - 1),
+            seconds(i.end_frame as i64),
+            direction_name(i.movement.approach),
+            turn_name(i.movement.turn),
+            i.count,
+            i.mean_delay
+        ));
+    }
+    out
+}
+
+fn signals_csv(log: &[SignalChange]) -> String {
+    let mut out = String::from("frame,time_s,approach,turn,state\n");
+    for c in log {
+        out.push_str(&format!(
+            "{},{},{},{},{}\n",
+            c.frame,
+            seconds(c.frame as i64),
+            direction_name(c.movement.approach),
+            turn_name(c.movement.turn),
+            light_state_name(c.state)
+        ));
+    }
+    out
+}
+
+// Single JSON document holding the same tables as the CSV files. Every
+// value is a number, boolean, null or one of the fixed lowercase names, so no
+// string escaping is needed.
+fn results_json(stats: &Statistics, intervals: &[IntervalCount], los: &LevelOfService) -> String {
+    let vehicles: Vec<String> = stats
+        .records
+        .iter()
+        .map(|r| {
+            format!(
+                "{{\"id\":{},\"approach\":\"{}\",\"turn\":\"{}\",\"spawn_frame\":{},\"exit_frame\":{},\"spawn_s\":{},\"exit_s\":{},\"stopped_s\":{},\"delay_s\":{},\"removed\":{}}}",
+                r.id,
+                direction_name(r.movement.approach),
+                turn_name(r.movement.turn),
+                r.spawn_frame,
+                r.exit_frame.map_or("null".to_string(), |f| f.to_string()),
+                seconds(r.spawn_frame as i64),
+                r.exit_frame.map_or("null".to_string(), |f| seconds(f as i64)),
+                seconds(r.stopped_frames as i64),
+                seconds(r.delay_frames as i64),
+                r.removed
+            )
+        })
+        .collect();
+    let intervals: Vec<String> = intervals
+        .iter()
+        .map(|i| {
+            format!(
+                "{{\"start_frame\":{},\"end_frame\":{},\"start_s\":{},\"end_s\":{},\"approach\":\"{}\",\"turn\":\"{}\",\"count\":{},\"mean_delay_s\":{:.3}}}",
+                i.start_frame,
+                i.end_frame,
+                seconds(i.start_frame as i64 - 1),
+                seconds(i.end_frame as i64),
+                direction_name(i.movement.approach),
+                turn_name(i.movement.turn),
+                i.count,
+                i.mean_delay
+            )
+        })
+        .collect();
+    let signals: Vec<String> = stats
+        .signal_log
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"frame\":{},\"time_s\":{},\"approach\":\"{}\",\"turn\":\"{}\",\"state\":\"{}\"}}",
+                c.frame,
+                seconds(c.frame as i64),
+                direction_name(c.movement.approach),
+                turn_name(c.movement.turn),
+                light_state_name(c.state)
+            )
+        })
+        .collect();
+    format!(
+        "{{\n\"schema_version\":{},\n\"frames_per_second\":{},\n\"vehicles\":[\n{}\n],\n\"intervals\":[\n{}\n],\n\"signals\":[\n{}\n],\n\"level_of_service\":{}\n}}\n",
+        SCHEMA_VERSION,
+        FRAMES_PER_SECOND,
+        vehicles.join(",\n"),
+        intervals.join(",\n"),
+        signals.join(",\n"),
+        los.json()
+    )
+}
+
+// One-document summary of a finished run for batch jobs: its length and seed
+// (a string, as it may not fit a JSON number exactly), vehicle counts,
+// throughput, delays and queue maxima overall, per approach and per
+// movement, and what the watchdog found
+pub fn summary_json(sim: &Simulation) -> String {
+    let stats = &sim.stats;
+    let frames = sim.frame() as i64 - 1;
+    let hours = frames as f64 / FRAMES_PER_SECOND as f64 / 3600.0;
+    let flow = |s: &FlowSummary| {
+        format!(
+            "\"throughput\":{},\"throughput_per_hour\":{:.1},\"mean_delay_s\":{:.3},\"p95_delay_s\":{:.3},\"max_queue\":{}",
+            s.throughput,
+            if hours > 0.0 {
+                s.throughput as f64 / hours
+            } else {
+                0.0
+            },
+            s.mean_delay,
+            s.p95_delay,
+            s.max_queue
+        )
+    };
+    let approaches: Vec<String> = stats
+        .by_approach()
+        .iter()
+        .map(|(d, s)| format!("{{\"approach\":\"{}\",{}}}", direction_name(*d), flow(s)))
+        .collect();
+    let movements: Vec<String> = stats
+        .by_movement()
+        .iter()
+        .map(|(m, s)| {
+            format!(
+                "{{\"approach\":\"{}\",\"turn\":\"{}\",{}}}",
+                direction_name(m.approach),
+                turn_name(m.turn),
+                flow(s)
+            )
+        })
+        .collect();
+    format!(
+        "{{\n\"schema_version\":{},\n\"seed\":\"{}\",\n\"frames\":{},\n\"duration_s\":{},\n\"spawned\":{},\n\"removed\":{},\n\"on_road\":{},\n\"overall\":{{{}}},\n\"box_entries_held\":{},\n\"stops_in_box\":{},\n\"stuck\":{{\"reported\":{},\"at_end\":{},\"deadlocks\":{}}},\n\"approaches\":[\n{}\n],\n\"movements\":[\n{}\n]\n}}\n",
+        SCHEMA_VERSION,
+        sim.seed(),
+        frames,
+        seconds(frames),
+        stats.spawned,
+        stats.records.iter().filter(|r| r.removed).count(),
+        sim.vehicles.len(),
+        flow(&stats.overall()),
+        stats.box_entries_held,
+        stats.box_blocked,
+        stats.stuck_vehicles,
+        sim.watchdog.stuck(&sim.vehicles).len(),
+        stats.deadlocks,
+        approaches.join(",\n"),
+        movements.join(",\n")
+    )
+}
@@ -0,0 +1,137 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::conflict::Movement;
+use crate::export::{direction_name, hold_name, turn_name};
+use crate::font::{self, GLYPH_HEIGHT};
+use crate::simulation::{FRAMES_PER_SECOND, Simulation};
+use crate::stats::{self, APPROACHES};
+
+const SCALE: u32 = 2;
+const LINE_HEIGHT: i32 = ((GLYPH_HEIGHT + 3) * SCALE) as i32;
+const PADDING: i32 = 6;
+
+// Text of the heads-up display: clock and viewer mode, vehicle counts,
+// queues per approach and each ring's phase with its time left
+pub fn lines(sim: &Simulation, fps: f64, mode: &str) -> Vec<String> {
+    let exited = sim
+        .stats
+        .records
+        .iter()
+        .filter(|r| r.exit_frame.is_some())
+        .count();
+    let queue = stats::queues(&sim.vehicles);
+    let by_approach = APPROACHES.map(|a| {
+        Movement::all()
+            .iter()
+            .filter(|m| m.approach == a)
+            .map(|m| queue[m.index()])
+            .sum::<u32>()
+    });
+
+    let mut lines = vec![
+        format!("{}  {}", clock(sim.frame() - 1), mode),
+        format!("Vehicles {}  FPS {:.0}", sim.vehicles.len(), fps),
+        format!("Spawned {}  Exited {}", sim.stats.spawned, exited),
+        format!(
+            "Queue NB {} SB {} EB {} WB {}",
+            by_approach[0], by_approach[1], by_approach[2], by_approach[3]
+        ),
+    ];
+    for status in sim.signals().status() {
+        let remaining = status
+            .remaining
+            .map(|f| format!(" {:.1}s", f as f64 / FRAMES_PER_SECOND as f64))
+            .unwrap_or_default();
+        lines.push(format!("{} {}{}", status.name, status.interval, remaining));
+    }
+    lines
+}
+
+// Text of the panel for a selected vehicle, or None once it has left
+pub fn vehicle_lines(sim: &Simulation, id: u32) -> Option<Vec<String>> {
+    let vehicle = sim.vehicles.iter().find(|v| v.id() == id)?;
+    let movement = vehicle.movement();
+    let waited = sim
+        .stats
+        .active()
+        .find(|r| r.id == id)
+        .map_or(0, |r| r.stopped_frames);
+    let mut lines = vec![
+        format!("Vehicle {}", id),
+        format!("Speed {} px/s", vehicle.speed() * FRAMES_PER_SECOND as i32),
+        format!(
+            "{}bound, {}",
+            direction_name(movement.approach),
+            turn_name(movement.turn)
+        ),
+        format!("Waiting {}", seconds(waited)),
+    ];
+    if let Some(hold) = vehicle.held {
+        lines.push(format!(
+            "Held {} {}",
+            hold_name(hold),
+            seconds(vehicle.stalled)
+        ));
+    }
+    Some(lines)
+}
+
+fn seconds(frames: u32) -> String {
+    format!("{:.1}s", frames as f64 / FRAMES_PER_SECOND as f64)
+}
+
+// mm:ss.s of simulated time
+fn clock(frames: i32) -> String {
+    let tenths = frames.max(0) as u32 * 10 / FRAMES_PER_SECOND;
+    format!(
+        "{:02}:{:02}.{}",
+        tenths / 600,
+        tenths / 10 % 60,
+        tenths % 10
+    )
+}
+
+// Panel in the top-left corner, below the playback indicator
+pub fn draw(canvas: &mut Canvas<Window>, lines: &[String]) {
+    panel(canvas, 6, 40, lines);
+}
+
+// Selected vehicle's panel in the top-right corner
+pub fn draw_vehicle(canvas: &mut Canvas<Window>, lines: &[String]) {
+    let (width, _) = canvas.output_size().unwrap_or((800, 800));
+    let x = width as i32 - 6 - panel_width(lines) as i32;
+    panel(canvas, x, 6, lines);
+}
+
+fn panel_width(lines: &[String]) -> u32 {
+    let text = lines
+        .iter()
+        .map(|l| font::text_width(l, SCALE))
+        .max()
+        .unwrap_or(0);
+    text + 2 * PADDING as u32
+}
+
+fn panel(canvas: &mut Canvas<Window>, x: i32, y: i32, lines: &[String]) {
+    let height = lines.len() as i32 * LINE_HEIGHT;
+    canvas.set_draw_color(Color::RGB(30, 30, 30));
+    let _ = canvas.fill_rect(Rect::new(
+        x,
+        y,
+        panel_width(lines),
+        (height + 2 * PADDING) as u32,
+    ));
+    for (i, line) in lines.iter().enumerate() {
+        font::draw_text(
+            canvas,
+            x + PADDING,
+            y + PADDING + i as i32 * LINE_HEIGHT,
+            line,
+            SCALE,
+            Color::WHITE,
+        );
+    }
+}
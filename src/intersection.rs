@@ -1,11 +1,24 @@
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use sdl2::rect::{Point, Rect};
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
 use crate::road::Road;
+use crate::vehicle::Direction;
+
+// The standard layout: two roads this wide crossing in the middle of a
+// square world this big, running edge to edge
+pub const WORLD_SIZE: u32 = 800;
+pub const ROAD_WIDTH: u32 = 100;
+
+// The rest of the geometry follows from those. The box where the roads
+// cross spans `BOX_MIN..BOX_MAX` on both axes, and each lane's centre line
+// runs `LANE_OFFSET` to one side of the middle of the world, `CENTER`.
+pub const BOX_MIN: i32 = ((WORLD_SIZE - ROAD_WIDTH) / 2) as i32;
+pub const BOX_MAX: i32 = BOX_MIN + ROAD_WIDTH as i32;
+pub const CENTER: i32 = (WORLD_SIZE / 2) as i32;
+pub const LANE_OFFSET: i32 = (ROAD_WIDTH / 4) as i32;
 
-#[allow(dead_code)]
 pub struct Intersection {
     pub road_ns: Road, // vertical road
     pub road_ew: Road, // horizontal road
@@ -15,11 +28,54 @@ impl Intersection {
     pub fn new(road_ns: Road, road_ew: Road) -> Self {
         Intersection { road_ns, road_ew }
     }
+    pub fn standard() -> Self {
+        Intersection::new(
+            Road::new_vertical(BOX_MIN, 0, ROAD_WIDTH, WORLD_SIZE), // North‑South road
+            Road::new_horizontal(0, BOX_MIN, WORLD_SIZE, ROAD_WIDTH), // East‑West road
+        )
+    }
+    // Everything the roads cover; vehicles enter at its edges and are gone
+    // once they've driven off them
+    pub fn bounds(&self) -> Rect {
+        self.road_ns.rect().union(self.road_ew.rect())
+    }
+    // Where the roads cross
+    pub fn square(&self) -> Rect {
+        (self.road_ns.rect())
+            .intersection(self.road_ew.rect())
+            .unwrap_or(self.road_ns.rect())
+    }
+    // The approach whose arm of the road contains `p`, as the direction its
+    // vehicles travel (a click on the southern arm means northbound)
+    pub fn approach_at(&self, p: Point) -> Option<Direction> {
+        let square = self.square();
+        if square.contains_point(p) {
+            None
+        } else if self.road_ns.contains(p) {
+            Some(if p.y > square.y() {
+                Direction::North
+            } else {
+                Direction::South
+            })
+        } else if self.road_ew.contains(p) {
+            Some(if p.x > square.x() {
+                Direction::West
+            } else {
+                Direction::East
+            })
+        } else {
+            None
+        }
+    }
     pub fn draw(&self, canvas: &mut Canvas<Window>) {
         let crossing_color = Color::RGB(100, 100, 100);
         canvas.set_draw_color(crossing_color);
         // Intersection square
-        let square = Rect::new(350, 350, 100, 100);
-        let _ = canvas.fill_rect(square);
+        let _ = canvas.fill_rect(self.square());
     }
 }
+
+// Bounds of the standard layout, which the simulation runs in
+pub fn world_bounds() -> Rect {
+    Intersection::standard().bounds()
+}
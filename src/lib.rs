@@ -0,0 +1,27 @@
+// src/lib.rs
+pub mod bindings;
+pub mod camera;
+pub mod conflict;
+pub mod debug;
+pub mod diagram;
+pub mod export;
+pub mod font;
+pub mod hud;
+pub mod intersection;
+pub mod los;
+pub mod nema;
+pub mod playback;
+pub mod replay;
+pub mod road;
+pub mod scenario;
+pub mod signal;
+pub mod simulation;
+pub mod snapshot;
+pub mod stats;
+pub mod sweep;
+pub mod timeline;
+pub mod traffic_light;
+pub mod trajectory;
+pub mod vehicle;
+pub mod watchdog;
+pub mod webster;
@@ -0,0 +1,377 @@
+use std::fmt::{self, Write};
+
+use crate::conflict::{MOVEMENT_COUNT, Movement};
+use crate::export::{direction_name, turn_name};
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::stats::{FlowSummary, Statistics};
+use crate::traffic_light::LightState;
+use crate::vehicle::Direction;
+
+// HCM level of service for signalised intersections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Grade {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl Grade {
+    // By mean control delay in seconds per vehicle: up to 10, 20, 35, 55 and
+    // 80 s for A to E, F beyond
+    pub fn from_delay(delay: f64) -> Grade {
+        match delay {
+            d if d <= 10.0 => Grade::A,
+            d if d <= 20.0 => Grade::B,
+            d if d <= 35.0 => Grade::C,
+            d if d <= 55.0 => Grade::D,
+            d if d <= 80.0 => Grade::E,
+            _ => Grade::F,
+        }
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// One approach or movement. Volumes and capacities are in vehicles per hour,
+// delays in seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LosRow {
+    pub volume: f64,
+    pub capacity: f64,
+    // None for the whole intersection, infinite with demand but no green
+    pub vc: Option<f64>,
+    pub control_delay: f64,
+    pub throughput: u32,
+    // None while no vehicle has finished its trip
+    pub grade: Option<Grade>,
+}
+
+impl LosRow {
+    fn new(volume: f64, capacity: f64, vc: Option<f64>, flow: &FlowSummary) -> Self {
+        let grade = if vc.is_some_and(|x| x > 1.0) {
+            Some(Grade::F)
+        } else {
+            (flow.throughput > 0).then(|| Grade::from_delay(flow.mean_delay))
+        };
+        LosRow {
+            volume,
+            capacity,
+            vc,
+            control_delay: flow.mean_delay,
+            throughput: flow.throughput,
+            grade,
+        }
+    }
+}
+
+// Level of service after a run, HCM style. Control delay is each vehicle's
+// travel time beyond free flow, so it includes slowing, stopping and
+// queueing. Volume is the vehicles that arrived per hour and a movement's
+// capacity its saturation flow times the share of the run it was green.
+// The turns of an approach share one lane, so the approach's v/c is the sum
+// of its movements'; its capacity is what the lane serves at the mix of
+// turns that arrived. A v/c over 1 is graded F whatever the delay; the
+// intersection is graded by delay alone.
+#[derive(Clone, Debug)]
+pub struct LevelOfService {
+    pub saturation: f64, // vehicles per hour of green per lane
+    pub approaches: Vec<(Direction, LosRow)>,
+    pub movements: Vec<(Movement, LosRow)>,
+    pub intersection: LosRow,
+}
+
+impl LevelOfService {
+    // Measured over the frames before `end_frame`
+    pub fn measure(stats: &Statistics, end_frame: i32, saturation: f64) -> Self {
+        let frames = (end_frame - 1).max(1) as f64;
+        let hours = frames / FRAMES_PER_SECOND as f64 / 3600.0;
+        let green = green_frames(stats, end_frame);
+        let mut arrivals = [0u32; MOVEMENT_COUNT];
+        for r in stats.records.iter().chain(stats.active()) {
+            arrivals[r.movement.index()] += 1;
+        }
+        let volume = |m: Movement| arrivals[m.index()] as f64 / hours;
+        let capacity = |m: Movement| saturation * green[m.index()] as f64 / frames;
+        let ratio = |m: Movement| match (volume(m), capacity(m)) {
+            (0.0, _) => 0.0,
+            (_, 0.0) => f64::INFINITY,
+            (v, c) => v / c,
+        };
+
+        let movements: Vec<(Movement, LosRow)> = stats
+            .by_movement()
+            .into_iter()
+            .map(|(m, flow)| {
+                (
+                    m,
+                    LosRow::new(volume(m), capacity(m), Some(ratio(m)), &flow),
+                )
+            })
+            .collect();
+        let approaches: Vec<(Direction, LosRow)> = stats
+            .by_approach()
+            .into_iter()
+            .map(|(approach, flow)| {
+                let turns: Vec<Movement> = Movement::all()
+                    .into_iter()
+                    .filter(|m| m.approach == approach)
+                    .collect();
+                let total: f64 = turns.iter().map(|m| volume(*m)).sum();
+                let vc: f64 = turns.iter().map(|m| ratio(*m)).sum();
+                // With no arrivals, the lane's capacity at an even mix
+                let capacity = if total > 0.0 {
+                    total / vc
+                } else {
+                    turns.len() as f64 / turns.iter().map(|m| 1.0 / capacity(*m)).sum::<f64>()
+                };
+                (approach, LosRow::new(total, capacity, Some(vc), &flow))
+            })
+            .collect();
+        let total: f64 = Movement::all().into_iter().map(volume).sum();
+        let capacity: f64 = approaches.iter().map(|(_, row)| row.capacity).sum();
+        LevelOfService {
+            saturation,
+            intersection: LosRow::new(total, capacity, None, &stats.overall()),
+            approaches,
+            movements,
+        }
+    }
+
+    // Plain-text table in the layout of `Statistics::report`
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "Level of service at a saturation flow of {:.0} veh/h of green per lane",
+            self.saturation
+        );
+        let _ = writeln!(
+            out,
+            "{:<16} {:>9} {:>9} {:>6} {:>10} {:>4}",
+            "", "veh/h", "capacity", "v/c", "ctrl delay", "LOS"
+        );
+        let mut row = |label: String, r: &LosRow| {
+            let _ = writeln!(
+                out,
+                "{:<16} {:>9.0} {:>9.0} {:>6} {:>9.1}s {:>4}",
+                label,
+                r.volume,
+                r.capacity,
+                r.vc.map_or("-".to_string(), |x| format!("{:.2}", x)),
+                r.control_delay,
+                r.grade.map_or("-".to_string(), |g| g.to_string())
+            );
+        };
+        for (approach, r) in &self.approaches {
+            row(format!("{:?}bound", approach), r);
+        }
+        for (m, r) in &self.movements {
+            row(format!("  {:?} {:?}", m.approach, m.turn), r);
+        }
+        row("Intersection".to_string(), &self.intersection);
+        let _ = writeln!(out, "LOS by control delay, F over capacity");
+        out
+    }
+
+    // One row per approach, movement and the intersection; `turn` is empty
+    // on approach rows and both are empty on the intersection's
+    pub fn csv(&self) -> String {
+        let mut out = String::from(
+            "approach,turn,volume_per_hour,capacity_per_hour,vc,control_delay_s,throughput,los\n",
+        );
+        for (approach, turn, r) in self.rows() {
+            out.push_str(&format!(
+                "{},{},{:.1},{:.1},{},{:.3},{},{}\n",
+                approach,
+                turn,
+                r.volume,
+                r.capacity,
+                r.vc.filter(|x| x.is_finite())
+                    .map_or(String::new(), |x| format!("{:.3}", x)),
+                r.control_delay,
+                r.throughput,
+                r.grade.map_or(String::new(), |g| g.to_string())
+            ));
+        }
+        out
+    }
+
+    // The same rows as JSON objects, with nulls for the empty fields
+    pub fn json(&self) -> String {
+        let rows: Vec<String> = self
+            .rows()
+            .into_iter()
+            .map(|(approach, turn, r)| {
+                let name = |s: &str| match s {
+                    "" => "null".to_string(),
+                    s => format!("\"{}\"", s),
+                };
+                format!(
+                    "{{\"approach\":{},\"turn\":{},\"volume_per_hour\":{:.1},\"capacity_per_hour\":{:.1},\"vc\":{},\"control_delay_s\":{:.3},\"throughput\":{},\"los\":{}}}",
+                    name(approach),
+                    name(turn),
+                    r.volume,
+                    r.capacity,
+                    r.vc.filter(|x| x.is_finite())
+                        .map_or("null".to_string(), |x| format!("{:.3}", x)),
+                    r.control_delay,
+                    r.throughput,
+                    r.grade.map_or("null".to_string(), |g| format!("\"{}\"", g))
+                )
+            })
+            .collect();
+        format!("[\n{}\n]", rows.join(",\n"))
+    }
+
+    fn rows(&self) -> Vec<(&'static str, &'static str, &LosRow)> {
+        let mut rows: Vec<_> = self
+            .approaches
+            .iter()
+            .map(|(d, r)| (direction_name(*d), "", r))
+            .collect();
+        rows.extend(
+            self.movements
+                .iter()
+                .map(|(m, r)| (direction_name(m.approach), turn_name(m.turn), r)),
+        );
+        rows.push(("", "", &self.intersection));
+        rows
+    }
+}
+
+// Frames each movement was green before `end_frame`, from the signal log
+fn green_frames(stats: &Statistics, end_frame: i32) -> [u32; MOVEMENT_COUNT] {
+    let mut green = [0u32; MOVEMENT_COUNT];
+    let mut since: [Option<i32>; MOVEMENT_COUNT] = [None; MOVEMENT_COUNT];
+    for change in &stats.signal_log {
+        let i = change.movement.index();
+        match (change.state == LightState::Green, since[i]) {
+            (true, None) => since[i] = Some(change.frame),
+            (false, Some(start)) => {
+                green[i] += (change.frame - start).max(0) as u32;
+                since[i] = None;
+            }
+            _ => {}
+        }
+    }
+    for (g, start) in green.iter_mut().zip(since) {
+        if let Some(start) = start {
+            *g += (end_frame - start).max(0) as u32;
+        }
+    }
+    green
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{SignalChange, VehicleRecord};
+    use crate::vehicle::Turn;
+
+    const HOUR: i32 = 3600 * FRAMES_PER_SECOND as i32;
+
+    fn flow(mean_delay: f64, throughput: u32) -> FlowSummary {
+        FlowSummary {
+            throughput,
+            mean_delay,
+            ..FlowSummary::default()
+        }
+    }
+
+    #[test]
+    fn grades_by_delay_at_each_boundary() {
+        for (delay, grade) in [
+            (0.0, Grade::A),
+            (10.0, Grade::A),
+            (10.1, Grade::B),
+            (20.0, Grade::B),
+            (20.1, Grade::C),
+            (35.0, Grade::C),
+            (35.1, Grade::D),
+            (55.0, Grade::D),
+            (55.1, Grade::E),
+            (80.0, Grade::E),
+            (80.1, Grade::F),
+            (500.0, Grade::F),
+        ] {
+            assert_eq!(Grade::from_delay(delay), grade, "{} s", delay);
+            assert_eq!(
+                LosRow::new(0.0, 0.0, Some(0.5), &flow(delay, 1)).grade,
+                Some(grade)
+            );
+        }
+    }
+
+    #[test]
+    fn over_capacity_is_f_whatever_the_delay() {
+        for (vc, delay, throughput, grade) in [
+            (Some(1.0), 5.0, 10, Some(Grade::A)),
+            (Some(1.01), 5.0, 10, Some(Grade::F)),
+            (Some(f64::INFINITY), 5.0, 0, Some(Grade::F)),
+            (Some(0.5), 5.0, 0, None),
+            (None, 5.0, 10, Some(Grade::A)),
+            (None, 90.0, 10, Some(Grade::F)),
+        ] {
+            let row = LosRow::new(0.0, 0.0, vc, &flow(delay, throughput));
+            assert_eq!(row.grade, grade, "v/c {:?}, {} s", vc, delay);
+        }
+    }
+
+    #[test]
+    fn volume_and_capacity_over_an_hour() {
+        let through = |approach| Movement {
+            approach,
+            turn: Turn::Straight,
+        };
+        let north = through(Direction::North);
+        let south = through(Direction::South);
+        let mut stats = Statistics::default();
+        // 475 northbound and 10 southbound an hour, 10 s of delay each
+        for (id, movement) in (0..485).map(|k| (k, if k < 475 { north } else { south })) {
+            stats.records.push(VehicleRecord {
+                id,
+                movement,
+                spawn_frame: 1,
+                exit_frame: Some(600),
+                stopped_frames: 0,
+                delay_frames: 600,
+                removed: false,
+            });
+        }
+        // Northbound green for half the hour, southbound never
+        for (frame, state) in [(1, LightState::Green), (1 + HOUR / 2, LightState::Red)] {
+            stats.signal_log.push(SignalChange {
+                frame,
+                movement: north,
+                state,
+            });
+        }
+
+        let los = LevelOfService::measure(&stats, 1 + HOUR, 1900.0);
+        let row = |m: Movement| &los.movements.iter().find(|(n, _)| *n == m).unwrap().1;
+        let n = row(north);
+        assert!((n.volume - 475.0).abs() < 1e-9);
+        assert!((n.capacity - 950.0).abs() < 1e-9);
+        assert!((n.vc.unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(n.grade, Some(Grade::A));
+        let s = row(south);
+        assert_eq!((s.capacity, s.vc), (0.0, Some(f64::INFINITY)));
+        assert_eq!(s.grade, Some(Grade::F));
+
+        let approach = |d: Direction| &los.approaches.iter().find(|(a, _)| *a == d).unwrap().1;
+        assert!((approach(Direction::North).capacity - 950.0).abs() < 1e-9);
+        assert!((approach(Direction::North).vc.unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(approach(Direction::South).grade, Some(Grade::F));
+        assert!((los.intersection.volume - 485.0).abs() < 1e-9);
+        assert_eq!(los.intersection.vc, None);
+        assert_eq!(los.intersection.grade, Some(Grade::A));
+        assert!(los.csv().contains("south,straight,10.0,0.0,,10.000,10,F\n"));
+    }
+}
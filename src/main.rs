@@ -1,35 +1,135 @@
 // src/main.rs
-mod intersection;
-mod road;
-mod traffic_light;
-mod vehicle;
-
-use rand::Rng;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
 use sdl2::pixels::Color;
-use std::collections::HashMap;
-use std::time::Duration;
+use sdl2::rect::{Point, Rect};
+use sdl2::video::FullscreenType;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use road_intersection::bindings::{self, Action, Bindings};
+use road_intersection::camera::Camera;
+use road_intersection::conflict::{ConflictMatrix, Movement};
+use road_intersection::debug;
+use road_intersection::export::{self, ExportOptions};
+use road_intersection::hud;
+use road_intersection::intersection::{BOX_MAX, BOX_MIN, Intersection};
+use road_intersection::los::LevelOfService;
+use road_intersection::playback::{self, Playback};
+use road_intersection::replay::{Input, Player, Session};
+use road_intersection::scenario::Scenario;
+use road_intersection::signal::SignalPlan;
+use road_intersection::simulation::{self, FRAMES_PER_SECOND, Simulation};
+use road_intersection::snapshot;
+use road_intersection::sweep::{self, Sweep};
+use road_intersection::timeline::{self, Timeline};
+use road_intersection::traffic_light::{LightState, TrafficLight};
+use road_intersection::trajectory::TrajectoryRecorder;
+use road_intersection::vehicle::{Direction, Turn};
+use road_intersection::webster::{self, Flows, Limits};
 
-use intersection::Intersection;
-use traffic_light::{LightState, TrafficLight};
-use vehicle::{Direction, Vehicle};
+fn main() -> Result<(), String> {
+    let args = parse_args(std::env::args().skip(1))?;
+    if args.help {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let scenario = match &args.scenario {
+        Some(path) => Scenario::load(path)?,
+        None => Scenario::default(),
+    };
+    match args.command {
+        Command::Run => {}
+        Command::Sweep => return run_sweep(&args, scenario),
+        Command::Webster => return run_webster(&args, &scenario),
+    }
+    // Last frame to simulate, if the run has a set length
+    let limit = match args.duration.or(scenario.duration) {
+        Some(seconds) => Some(simulation::frames(seconds).ok_or("the duration is too long")?),
+        None => None,
+    };
+    let mut trajectory = match &args.trajectory {
+        Some(path) => Some(
+            TrajectoryRecorder::create(path, args.trajectory_every).map_err(|e| e.to_string())?,
+        ),
+        None => None,
+    };
 
-use road::Road;
+    // The scenario's controller, checked against the movement conflicts
+    // before we start
+    let signals = scenario.controller()?;
+    let new_sim = |seed| {
+        let mut sim = Simulation::with_seed(signals.clone(), seed);
+        scenario.set_up(&mut sim);
+        sim
+    };
 
-const SAFE_DISTANCE: i32 = 50; // Minimum safe distance between vehicles
+    // Replaying a recorded session, or running live and recording the inputs
+    let mut player = match &args.replay {
+        Some(path) => {
+            let session = Session::load(path)?;
+            session
+                .check(&scenario)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Some(Player::new(session))
+        }
+        None => None,
+    };
+    let mut sim = match &player {
+        Some(_) if args.seed.is_some() => {
+            return Err("--seed can't be used with --replay, which has its own".to_string());
+        }
+        Some(player) => new_sim(player.session().seed),
+        None => new_sim(args.seed.or(scenario.seed).unwrap_or_else(rand::random)),
+    };
+    let seed = sim.seed();
+    let mut session = Session::new(seed, &scenario);
+
+    // Without a window the run goes as fast as it can, to its set length or
+    // the end of the replay
+    if args.headless {
+        let end = match (limit, &player) {
+            (Some(limit), _) => limit + 1,
+            (None, Some(player)) => player.session().end_frame,
+            (None, None) => {
+                return Err("--headless needs a --duration or a scenario with one".to_string());
+            }
+        };
+        while sim.frame() < end && !player.as_ref().is_some_and(|p| p.finished(&sim)) {
+            advance(&mut sim, player.as_mut(), trajectory.as_mut())?;
+        }
+        return finish(&args, &scenario, &sim, session, trajectory);
+    }
 
-fn main() -> Result<(), String> {
     // Initialize SDL2 context and video subsystem
     let sdl_context = sdl2::init()?;
+
+    // Key bindings: the defaults, changed by a bindings file if there is one
+    let default_keys = Path::new(bindings::DEFAULT_PATH);
+    let bindings = match &args.keys {
+        Some(path) => Bindings::load(path)?,
+        None if default_keys.exists() => Bindings::load(default_keys)?,
+        None => Bindings::default(),
+    };
     let video_subsystem = sdl_context.video()?;
 
+    // The world's size comes from the road layout; the window starts at that
+    // size and can be resized or made fullscreen, the scene scaling to fit
+    let intersection = Intersection::standard();
+    let world = intersection.bounds();
+
     // Create window and canvas
-    let window = video_subsystem
-        .window("Road Intersection", 800, 800)
+    let (width, height) = args.window.unwrap_or((world.width(), world.height()));
+    let mut window = video_subsystem
+        .window("Road Intersection", width, height)
         .position_centered()
+        .resizable()
         .build()
         .map_err(|e| e.to_string())?;
+    window
+        .set_minimum_size(world.width() / 2, world.height() / 2)
+        .map_err(|e| e.to_string())?;
 
     let mut canvas = window
         .into_canvas()
@@ -41,67 +141,259 @@ fn main() -> Result<(), String> {
     canvas.clear();
     canvas.present();
 
-    // Instantiate our modular components
-    let road_ns = Road::new_vertical(350, 0, 100, 800); // North‑South road 
-    let road_ew = Road::new_horizontal(0, 350, 800, 100); // East‑West road 
-    let intersection = Intersection::new(road_ns, road_ew);
-
-    let mut light_s = TrafficLight::new(460, 460, 20, 20, LightState::Red);
-    let mut light_w = TrafficLight::new(320, 460, 20, 20, LightState::Red);
-    let mut light_n = TrafficLight::new(320, 320, 20, 20, LightState::Green);
-    let mut light_e = TrafficLight::new(460, 320, 20, 20, LightState::Red);
-    let mut vehicles = Vec::new();
-
-    // Track the last spawn time for each direction to enforce safe distance
-    let mut last_spawn = HashMap::new();
-    last_spawn.insert(Direction::North, 0);
-    last_spawn.insert(Direction::South, 0);
-    last_spawn.insert(Direction::East, 0);
-    last_spawn.insert(Direction::West, 0);
-
-    let mut rng = rand::rng();
+    // The world is drawn into this texture at full size, then the camera's
+    // view of it is scaled into the window; panels are drawn on top
+    let texture_creator = canvas.texture_creator();
+    let mut scene = texture_creator
+        .create_texture_target(None, world.width(), world.height())
+        .map_err(|e| e.to_string())?;
+    let mut camera = Camera::new(world);
+
+    // Three-aspect heads in the corners of the box, countdowns on their outer
+    // side, each showing its approach's through movement
+    let through = |approach| Movement {
+        approach,
+        turn: Turn::Straight,
+    };
+    let left = |approach| Movement {
+        approach,
+        turn: Turn::Left,
+    };
+    let (lo, hi) = (BOX_MIN, BOX_MAX);
+    let mut heads = [
+        (
+            TrafficLight::new(hi + 6, hi + 6, 18, 50, LightState::Red)
+                .with_countdown_at(hi + 4, hi + 60),
+            through(Direction::North),
+        ),
+        (
+            TrafficLight::new(lo - 24, hi + 6, 18, 50, LightState::Red)
+                .with_countdown_at(lo - 26, hi + 60),
+            through(Direction::East),
+        ),
+        (
+            TrafficLight::new(lo - 24, lo - 56, 18, 50, LightState::Green)
+                .with_countdown_at(lo - 26, lo - 74),
+            through(Direction::South),
+        ),
+        (
+            TrafficLight::new(hi + 6, lo - 56, 18, 50, LightState::Red)
+                .with_countdown_at(hi + 4, lo - 74),
+            through(Direction::West),
+        ),
+        // Protected-left arrows beside each head, pointing where the turn exits
+        (
+            TrafficLight::new_arrow(lo - 48, lo - 26, 20, 20, LightState::Red, Direction::East)
+                .with_countdown_at(lo - 50, lo - 44),
+            left(Direction::South),
+        ),
+        (
+            TrafficLight::new_arrow(hi + 28, hi + 6, 20, 20, LightState::Red, Direction::West)
+                .with_countdown_at(hi + 28, hi + 30),
+            left(Direction::North),
+        ),
+        (
+            TrafficLight::new_arrow(hi + 28, lo - 26, 20, 20, LightState::Red, Direction::South)
+                .with_countdown_at(hi + 28, lo - 44),
+            left(Direction::West),
+        ),
+        (
+            TrafficLight::new_arrow(lo - 48, hi + 6, 20, 20, LightState::Red, Direction::North)
+                .with_countdown_at(lo - 50, hi + 30),
+            left(Direction::East),
+        ),
+    ];
+
+    let mut timeline = Timeline::new(timeline::DEFAULT_SECONDS);
+    let mut playback = Playback::default();
+    if let Some(speed) = args.speed {
+        playback.set_speed(speed);
+    }
+    let mut title = String::new();
+    let mut show_hud = true;
+    let mut show_countdown = true;
+    let mut show_debug = false;
+    // Clicking a head forces its state while override mode is on
+    let mut override_mode = false;
+    let mut selected: Option<u32> = None;
+    // Frames drawn in the current second, and the rate over the last one
+    let (mut fps, mut fps_frames, mut fps_since) = (0.0, 0, Instant::now());
+
     let mut event_pump = sdl_context.event_pump()?;
-    let mut n = 0;
-    let mut frame_count = 0;
-    let mut current_light: u8 = 1;
 
     'running: loop {
-        frame_count += 1;
-
         // Input handling
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(keycode),
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
                     ..
                 } => {
-                    let direction = match keycode {
-                        Keycode::Up => Some(Direction::North), // From south heading north
-                        Keycode::Down => Some(Direction::South), // From north heading south
-                        Keycode::Left => Some(Direction::West), // From east heading west
-                        Keycode::Right => Some(Direction::East), // From west heading east
-                        Keycode::R => {
-                            // Random direction
-                            let random_dir = match rng.random_range(0..4) {
-                                0 => Direction::North,
-                                1 => Direction::South,
-                                2 => Direction::East,
-                                3 => Direction::West,
-                                _ => unreachable!(),
-                            };
-                            Some(random_dir)
+                    let screen = canvas.output_size()?;
+                    let p = camera.to_world(Point::new(x, y), screen);
+                    let view = timeline.shown().unwrap_or(&sim);
+                    let hit = view
+                        .vehicles
+                        .iter()
+                        .find(|v| v.rect().contains_point(p))
+                        .map(|v| v.id());
+                    // Heads, spawning and overrides change the run, so only
+                    // live; selecting a vehicle works in every mode
+                    let live = player.is_none() && !timeline.scrubbing();
+                    let head = heads.iter().find(|(light, _)| light.contains(p));
+                    if let (true, true, Some((_, movement))) = (live, override_mode, head) {
+                        let next = match sim.indication(*movement).state {
+                            _ if !sim.overridden(*movement) => Some(LightState::Green),
+                            LightState::Green => Some(LightState::Yellow),
+                            LightState::Yellow => Some(LightState::Red),
+                            LightState::Red => None,
+                        };
+                        for m in controlled(*movement) {
+                            apply_live(&mut session, &mut sim, Input::Override(m, next));
                         }
-                        Keycode::Escape => break 'running, // Exit the simulation
-                        _ => None,
+                    } else if let Some(id) = hit {
+                        selected = Some(id);
+                    } else if let (true, Some(direction)) = (live, intersection.approach_at(p)) {
+                        apply_live(&mut session, &mut sim, Input::Spawn(direction));
+                    } else {
+                        selected = None;
+                    }
+                }
+                // Wheel zooms about the pointer, right or middle drag pans
+                Event::MouseWheel {
+                    y,
+                    direction,
+                    mouse_x,
+                    mouse_y,
+                    ..
+                } => {
+                    let notches = match direction {
+                        MouseWheelDirection::Flipped => -y,
+                        _ => y,
                     };
-
-                    // If a valid direction key was pressed, try to spawn a vehicle
-                    if let Some(dir) = direction {
-                        // Check if it's safe to spawn a new vehicle (not too close to existing ones)
-                        if is_safe_to_spawn(&vehicles, dir, &last_spawn, frame_count) {
-                            vehicles.push(Vehicle::new(dir));
-                            last_spawn.insert(dir, frame_count);
+                    let screen = canvas.output_size()?;
+                    camera.zoom_at(notches, Point::new(mouse_x, mouse_y), screen);
+                }
+                Event::MouseMotion {
+                    mousestate,
+                    xrel,
+                    yrel,
+                    ..
+                } if mousestate.right() || mousestate.middle() => {
+                    let screen = canvas.output_size()?;
+                    camera.pan(xrel, yrel, screen);
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    for action in bindings.actions(keycode) {
+                        // Inputs that change the run only apply live; a
+                        // replay follows its recording and a rewound frame
+                        // is only looked at until Enter resumes from it
+                        let live = player.is_none() && !timeline.scrubbing();
+                        let second = FRAMES_PER_SECOND as i32;
+                        let spawn = match action {
+                            Action::SpawnNorth => Some(Input::Spawn(Direction::North)),
+                            Action::SpawnSouth => Some(Input::Spawn(Direction::South)),
+                            Action::SpawnEast => Some(Input::Spawn(Direction::East)),
+                            Action::SpawnWest => Some(Input::Spawn(Direction::West)),
+                            Action::SpawnRandom => Some(Input::SpawnRandom),
+                            _ => None,
+                        };
+                        match action {
+                            Action::Quit => break 'running,
+                            Action::SaveSnapshot => match snapshot::save(&sim, &args.snapshot) {
+                                Ok(()) => println!("Saved snapshot to {}", args.snapshot.display()),
+                                Err(e) => eprintln!("Can't save snapshot: {}", e),
+                            },
+                            // A replay or a recording must follow its own inputs only
+                            Action::LoadSnapshot if player.is_some() || args.record.is_some() => {
+                                eprintln!("Snapshots can't be loaded while recording or replaying")
+                            }
+                            Action::LoadSnapshot => {
+                                match snapshot::load(&mut sim, &args.snapshot) {
+                                    Ok(()) => {
+                                        timeline = Timeline::new(timeline::DEFAULT_SECONDS);
+                                        println!("Loaded snapshot from {}", args.snapshot.display())
+                                    }
+                                    Err(e) => eprintln!("Can't load snapshot: {}", e),
+                                }
+                            }
+                            // Pause, single step, speed and the view work in every mode
+                            Action::Pause => playback.toggle_pause(),
+                            Action::Step => playback.step_once(),
+                            Action::Slower => playback.slower(),
+                            Action::Faster => playback.faster(),
+                            Action::ToggleHud => show_hud = !show_hud,
+                            Action::ToggleCountdown => show_countdown = !show_countdown,
+                            Action::ToggleDebug => show_debug = !show_debug,
+                            Action::Follow => camera.following = !camera.following,
+                            Action::ResetCamera => camera.reset(),
+                            Action::Fullscreen => {
+                                let window = canvas.window_mut();
+                                let next = match window.fullscreen_state() {
+                                    FullscreenType::Off => FullscreenType::Desktop,
+                                    _ => FullscreenType::Off,
+                                };
+                                if let Err(e) = window.set_fullscreen(next) {
+                                    eprintln!("Can't change fullscreen mode: {}", e);
+                                }
+                            }
+                            // Seeking in a replay
+                            Action::SeekBack | Action::SeekForward | Action::Restart => {
+                                if let Some(player) = player.as_mut() {
+                                    let target = match action {
+                                        Action::SeekBack => sim.frame() - 5 * second,
+                                        Action::SeekForward => sim.frame() + 5 * second,
+                                        _ => 1,
+                                    };
+                                    player.seek(&mut sim, target, || new_sim(seed));
+                                    // Seeking back replays frames already logged
+                                    if let Some(recorder) = trajectory.as_mut() {
+                                        recorder
+                                            .truncate(sim.frame())
+                                            .map_err(|e| e.to_string())?;
+                                    }
+                                }
+                            }
+                            // Scrub through the last seconds, Enter carries on from there
+                            Action::RewindBack if player.is_none() => {
+                                timeline.back(second as usize)
+                            }
+                            Action::RewindForward if player.is_none() => {
+                                timeline.forward(second as usize)
+                            }
+                            Action::Resume if player.is_none() => {
+                                timeline.resume(&mut sim);
+                                session.truncate(sim.frame());
+                                if let Some(recorder) = trajectory.as_mut() {
+                                    recorder.truncate(sim.frame()).map_err(|e| e.to_string())?;
+                                }
+                            }
+                            // Leaving override mode hands every head back to the controller
+                            Action::ToggleOverride if live => {
+                                override_mode = !override_mode;
+                                if !override_mode {
+                                    for m in Movement::all() {
+                                        if sim.overridden(m) {
+                                            apply_live(
+                                                &mut session,
+                                                &mut sim,
+                                                Input::Override(m, None),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                            _ => {
+                                if let (true, Some(input)) = (live, spawn) {
+                                    apply_live(&mut session, &mut sim, input);
+                                }
+                            }
                         }
                     }
                 }
@@ -109,179 +401,565 @@ fn main() -> Result<(), String> {
             }
         }
 
-        // Update logic
-        n += 1;
-        if n > 150 {
-            // Turn all lights red
-            light_n.update(false);
-            light_s.update(false);
-            light_e.update(false);
-            light_w.update(false);
-
-            match current_light {
-                0 => light_n.update(true),
-                1 => light_e.update(true),
-                2 => light_s.update(true),
-                3 => light_w.update(true),
-                _ => (),
+        // Update logic: as many steps as the speed asks for this frame
+        let steps = if timeline.scrubbing() {
+            0
+        } else {
+            playback.steps()
+        };
+        for _ in 0..steps {
+            if limit.is_some_and(|limit| sim.frame() > limit) {
+                break 'running;
+            }
+            advance(&mut sim, player.as_mut(), trajectory.as_mut())?;
+            if player.is_none() {
+                timeline.push(&sim);
             }
-
-            // Move to the next light
-            current_light = (current_light + 1) % 4;
-            n = 0;
         }
-        // Inside the main loop's update section:
 
-        // Compute tentative positions (with traffic light checks)
-        let tentatives: Vec<Vehicle> = vehicles
-            .iter()
-            .map(|v| {
-                let mut tentative_v = v.clone();
-                let light_state = match tentative_v.direction() {
-                    Direction::North => light_s.state(),
-                    Direction::South => light_n.state(),
-                    Direction::East => light_w.state(),
-                    Direction::West => light_e.state(),
-                };
-
-                // Only move if not stopped by light or vehicle ahead
-                tentative_v.update(light_state);
-                tentative_v
-            })
-            .collect();
+        // While scrubbing, show the rewound frame instead of the live one
+        let view = timeline.shown().unwrap_or(&sim);
 
-        // Check safe distances between vehicles in the same direction
-        let mut safe_to_move = vec![true; tentatives.len()];
-        for i in 0..tentatives.len() {
-            let current = &tentatives[i];
-            let dir = current.direction();
-
-            // Find closest vehicle ahead in the same direction
-            let closest_ahead = tentatives
-                .iter()
-                .enumerate()
-                .filter(|(j, other)| *j != i && other.direction() == dir)
-                .filter_map(|(_, other)| {
-                    let distance = match dir {
-                        Direction::North => {
-                            current.rect.y() - (other.rect.y() + other.rect.height() as i32)
-                        }
-                        Direction::South => {
-                            other.rect.y() - (current.rect.y() + current.rect.height() as i32)
-                        }
-                        Direction::East => {
-                            other.rect.x() - (current.rect.x() + current.rect.width() as i32)
-                        }
-                        Direction::West => {
-                            current.rect.x() - (other.rect.x() + other.rect.width() as i32)
-                        }
-                    };
-                    if distance >= 0 { Some(distance) } else { None }
-                })
-                .min();
-
-            // Block movement if vehicle is too close
-            if let Some(distance) = closest_ahead {
-                if distance < SAFE_DISTANCE {
-                    safe_to_move[i] = false;
-                }
-            }
+        for (light, movement) in heads.iter_mut() {
+            light.update(view.indication(*movement), view.overridden(*movement));
         }
 
-        // Inside the main loop's collision check:
-        let mut collisions = vec![false; tentatives.len()];
-        for i in 0..tentatives.len() {
-            for j in (i + 1)..tentatives.len() {
-                let a = &tentatives[i];
-                let b = &tentatives[j];
+        // Keep the followed vehicle in the middle while it's on the road
+        let followed = selected.and_then(|id| view.vehicles.iter().find(|v| v.id() == id));
+        if let (true, Some(v)) = (camera.following, followed) {
+            camera.look_at(v.rect().center());
+        }
 
-                // Allow vehicles in the intersection to pass through
-                if (a.in_intersection || b.in_intersection) && !a.has_turned && !b.has_turned {
-                    continue; // Skip collision check if either is in the intersection
+        // Drawing
+        canvas
+            .with_texture_canvas(&mut scene, |canvas| {
+                canvas.set_draw_color(Color::RGB(0, 0, 0));
+                canvas.clear();
+                intersection.road_ns.draw(canvas);
+                intersection.road_ew.draw(canvas);
+                intersection.draw(canvas);
+                // Draw lights on top
+                for (light, _) in &heads {
+                    light.draw(canvas, show_countdown);
                 }
-
-                if a.rect().has_intersection(b.rect()) {
-                    collisions[i] = true;
-                    collisions[j] = true;
+                for vehicle in &view.vehicles {
+                    vehicle.draw(canvas);
+                }
+                // Selected vehicle gets a white frame
+                if let Some(v) = followed {
+                    let r = v.rect();
+                    canvas.set_draw_color(Color::WHITE);
+                    let _ = canvas.draw_rect(Rect::new(
+                        r.x() - 3,
+                        r.y() - 3,
+                        r.width() + 6,
+                        r.height() + 6,
+                    ));
+                }
+                if show_debug {
+                    debug::draw(canvas, view);
                 }
+            })
+            .map_err(|e| e.to_string())?;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        let screen = canvas.output_size()?;
+        canvas.copy(&scene, camera.view(), camera.viewport(screen))?;
+
+        // Panels stay put whatever the camera does
+        if show_debug {
+            debug::draw_legend(&mut canvas);
+        }
+        playback.draw(&mut canvas);
+        if show_hud {
+            let mode = if timeline.scrubbing() {
+                "Rewind".to_string()
+            } else if player.is_some() {
+                format!("Replay {}", playback.label())
+            } else if override_mode {
+                format!("{} Override", playback.label())
+            } else {
+                playback.label()
+            };
+            let mut lines = hud::lines(view, fps, &mode);
+            if camera.zoom() > 1.0 || camera.following {
+                let follow = if camera.following { "  Follow" } else { "" };
+                lines.push(format!("Zoom {:.1}x{}", camera.zoom(), follow));
             }
+            hud::draw(&mut canvas, &lines);
+        }
+        if let Some(lines) = selected.and_then(|id| hud::vehicle_lines(view, id)) {
+            hud::draw_vehicle(&mut canvas, &lines);
         }
+        canvas.present();
 
-        // Update original vehicles only if safe
-        for (i, vehicle) in vehicles.iter_mut().enumerate() {
-            if safe_to_move[i] && !collisions[i] {
-                *vehicle = tentatives[i].clone();
-            }
+        fps_frames += 1;
+        let elapsed = fps_since.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            fps = fps_frames as f64 / elapsed;
+            (fps_frames, fps_since) = (0, Instant::now());
         }
-        // Remove vehicles that have left the screen
-        vehicles.retain(|v| is_on_screen(v));
 
-        // Drawing
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        road_ns.draw(&mut canvas);
-        road_ew.draw(&mut canvas);
-        intersection.draw(&mut canvas);
-        // Draw lights on top
-        light_n.draw(&mut canvas);
-        light_s.draw(&mut canvas);
-        light_e.draw(&mut canvas);
-        light_w.draw(&mut canvas);
-        for vehicle in &vehicles {
-            vehicle.draw(&mut canvas);
+        let label = format!("Road Intersection - {}", playback.label());
+        if label != title {
+            let _ = canvas.window_mut().set_title(&label);
+            title = label;
         }
-        canvas.present();
 
         std::thread::sleep(Duration::from_millis(16));
     }
+
+    finish(&args, &scenario, &sim, session, trajectory)
+}
+
+// One step of the run, live or from the replay, logged to the trajectory
+fn advance(
+    sim: &mut Simulation,
+    player: Option<&mut Player>,
+    trajectory: Option<&mut TrajectoryRecorder>,
+) -> Result<(), String> {
+    let frame = sim.frame();
+    match player {
+        Some(player) => player.step(sim),
+        None => sim.step(),
+    }
+    if sim.frame() != frame
+        && let Some(recorder) = trajectory
+    {
+        recorder
+            .record(frame, &sim.vehicles)
+            .map_err(|e| e.to_string())?;
+    }
+    for event in sim.watchdog_events.drain(..) {
+        eprintln!("{}", event);
+    }
     Ok(())
 }
 
-fn is_safe_to_spawn(
-    vehicles: &[Vehicle],
-    direction: Direction,
-    last_spawn: &HashMap<Direction, i32>,
-    current_frame: i32,
-) -> bool {
-    // Enforce minimum time between spawns in the same direction
-    if let Some(last_frame) = last_spawn.get(&direction) {
-        if current_frame - last_frame < SAFE_DISTANCE / 2 {
-            return false;
+// Once the run ends: save the recording, report, close the trajectory and
+// write the exports. A headless run reports with the JSON summary on stdout,
+// everything else going to stderr so the output can be piped straight on.
+fn finish(
+    args: &Args,
+    scenario: &Scenario,
+    sim: &Simulation,
+    mut session: Session,
+    trajectory: Option<TrajectoryRecorder>,
+) -> Result<(), String> {
+    let note = |message: String| match args.headless {
+        true => eprintln!("{}", message),
+        false => println!("{}", message),
+    };
+    if let Some(path) = &args.record {
+        session.end_frame = sim.frame();
+        session.save(path).map_err(|e| e.to_string())?;
+        note(format!("Recorded session to {}", path.display()));
+    }
+    let summary = export::summary_json(sim);
+    let saturation = args.saturation.unwrap_or(scenario.saturation);
+    let los = LevelOfService::measure(&sim.stats, sim.frame(), saturation);
+    if args.headless {
+        print!("{}", summary);
+    } else {
+        print!("{}", sim.stats.report());
+    }
+    note(los.report().trim_end().to_string());
+    if let Some(path) = &args.summary {
+        fs::write(path, &summary).map_err(|e| format!("{}: {}", path.display(), e))?;
+        note(format!("Wrote {}", path.display()));
+    }
+    if let Some(recorder) = trajectory {
+        recorder.finish().map_err(|e| e.to_string())?;
+    }
+    if let Some(options) = &args.export {
+        let options = ExportOptions {
+            saturation,
+            ..options.clone()
+        };
+        let written =
+            export::export(&sim.stats, sim.frame(), &options).map_err(|e| e.to_string())?;
+        for path in written {
+            note(format!("Wrote {}", path.display()));
         }
     }
+    Ok(())
+}
 
-    // Calculate spawn point based on direction
-    let (spawn_coord, is_vertical) = match direction {
-        Direction::North => (800, true), // Spawn at bottom (y=800)
-        Direction::South => (-40, true), // Spawn at top (y=-40)
-        Direction::East => (-40, false), // Spawn at left (x=-40)
-        Direction::West => (800, false), // Spawn at right (x=800)
+// Every combination of the `--vary` settings with every seed, in parallel,
+// summarised into one table
+fn run_sweep(args: &Args, base: Scenario) -> Result<(), String> {
+    if args.replay.is_some() || args.record.is_some() || args.trajectory.is_some() {
+        return Err("sweep can't record, replay or log trajectories".to_string());
+    }
+    let duration = args
+        .duration
+        .or(base.duration)
+        .ok_or("sweep needs a --duration or a scenario with one")?;
+    let first = args.seed.or(base.seed).unwrap_or(1);
+    let sweep = Sweep {
+        base,
+        vary: args.vary.clone(),
+        seeds: (0..args.seeds as u64)
+            .map(|i| first.wrapping_add(i))
+            .collect(),
+        duration,
     };
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let started = Instant::now();
+    let results = sweep.run(jobs)?;
+    println!(
+        "Ran {} cases x {} seeds on {} threads in {:.1}s",
+        results.len(),
+        sweep.seeds.len(),
+        jobs,
+        started.elapsed().as_secs_f64()
+    );
+    let dir = match &args.export {
+        Some(options) => options.dir.clone(),
+        None => ExportOptions::default().dir,
+    };
+    let path = dir.join("sweep.csv");
+    fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(&path, sweep::csv(&sweep.vary, &results)))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
 
-    // Check distance from existing vehicles in the same direction
-    for vehicle in vehicles.iter().filter(|v| v.direction() == direction) {
-        let vehicle_pos = if is_vertical {
-            vehicle.rect.y() + vehicle.rect.height() as i32
-        } else {
-            vehicle.rect.x() + vehicle.rect.width() as i32
-        };
-
-        let distance = (vehicle_pos - spawn_coord).abs();
-        if distance < SAFE_DISTANCE {
-            return false; // Existing vehicle too close to spawn point
+// Time the scenario's fixed-time phases (or the generated ones) with
+// Webster's method and write the plan where a scenario can load it
+fn run_webster(args: &Args, scenario: &Scenario) -> Result<(), String> {
+    let saturation = args.saturation.unwrap_or(scenario.saturation);
+    let flows = match (&args.flows, &args.measured) {
+        (Some(_), Some(_)) => return Err("use either --flows or --measured".to_string()),
+        (Some(path), None) => Flows::load(path, saturation)?,
+        (None, Some(path)) => {
+            let csv = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Flows::measured(&csv, saturation).map_err(|e| format!("{}: {}", path.display(), e))?
         }
+        (None, None) => Flows::from_demand(scenario.rates(), saturation),
+    };
+    let plan = match scenario.plan()? {
+        Some(plan) => plan,
+        None => SignalPlan::generate(&ConflictMatrix::from_geometry(), FRAMES_PER_SECOND),
+    };
+    let timing = webster::optimise(&plan, &flows, &args.limits)?;
+
+    let seconds = |frames: u32| frames as f64 / FRAMES_PER_SECOND as f64;
+    println!("{:<6} {:>6} {:>7}  movements", "phase", "y", "green");
+    for (i, (phase, y)) in timing.plan.phases.iter().zip(&timing.critical).enumerate() {
+        let movements: Vec<String> = phase
+            .movements
+            .iter()
+            .map(|m| format!("{:?} {:?}", m.approach, m.turn))
+            .collect();
+        println!(
+            "{:<6} {:>6.3} {:>6.1}s  {}",
+            i + 1,
+            y,
+            seconds(phase.green),
+            movements.join(", ")
+        );
     }
+    println!(
+        "Y = {:.3}, optimal cycle {:.1}s, cycle {:.1}s",
+        timing.total,
+        timing.optimal_cycle,
+        timing.cycle()
+    );
+
+    let dir = match &args.export {
+        Some(options) => options.dir.clone(),
+        None => ExportOptions::default().dir,
+    };
+    let path = dir.join("plan.txt");
+    let text = format!(
+        "# Webster plan: Y {:.3}, cycle {:.1} s, lost time {:.1} s per phase\n{}",
+        timing.total,
+        timing.cycle(),
+        args.limits.lost_time,
+        timing.plan.to_text()
+    );
+    fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(&path, text))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+// Record a live input in the session and apply it
+fn apply_live(session: &mut Session, sim: &mut Simulation, input: Input) {
+    session.record(sim.frame(), input);
+    input.apply(sim);
+}
+
+// Movements a head controls: a through head also carries the right turn
+fn controlled(movement: Movement) -> Vec<Movement> {
+    match movement.turn {
+        Turn::Straight => vec![
+            movement,
+            Movement {
+                turn: Turn::Right,
+                ..movement
+            },
+        ],
+        _ => vec![movement],
+    }
+}
 
-    true
+const USAGE: &str = "\
+Usage: road_intersection [run] [OPTIONS]
+       road_intersection sweep --duration SECONDS [--vary NAME=V1,V2,...]... [OPTIONS]
+       road_intersection webster [--flows FILE | --measured vehicles.csv] [OPTIONS]
+
+Run:
+  --scenario FILE         seed, length, demand and signals from a scenario file
+  --seed N                seed for the run, overriding the scenario's
+  --duration SECONDS      stop after this much simulated time
+  --headless              no window: run as fast as possible, then print a JSON summary
+  --speed X               starting speed in the window, one of 0.25 0.5 1 2 4 8 16
+  --window WxH            starting window size, e.g. 1200x1200
+
+Sweep:
+  --vary NAME=V1,V2,...   run every listed value of a scenario setting, e.g.
+                          signals.cycle=60,90 or demand.scale=0.5,1,1.5
+  --seeds N               runs per combination, seeds counting up from --seed (default 10)
+  --jobs N                threads to run on (default: every core)
+
+Webster:
+  --flows FILE            veh/h per movement, lines 'approach turn volume [saturation]'
+  --measured FILE         arrivals counted in an exported vehicles.csv
+                          (without either, the scenario's demand is used)
+  --saturation N          saturation flow in veh/h of green per lane, overriding the
+                          scenario's (default 1900); also used for a run's level of service
+  --lost-time SECONDS     start-up time lost per phase (default 2)
+  --min-cycle SECONDS     shortest cycle to allow (default 30)
+  --max-cycle SECONDS     longest cycle to allow (default 120)
+
+Output:
+  --summary FILE          also write the JSON summary to FILE
+  --out DIR               write results to DIR (default results/, CSV unless --export;
+                          a sweep writes DIR/sweep.csv, webster DIR/plan.txt)
+  --export FORMATS        write results as csv, json and/or an svg timing diagram
+                          (e.g. csv,json,svg)
+  --trajectory FILE       log every vehicle each frame (.jsonl for JSON Lines, else CSV)
+  --trajectory-every N    only log every Nth frame
+  --record FILE           save the seed and inputs on exit
+  --replay FILE           play a recorded session back
+
+Viewer:
+  --snapshot FILE         where F5 saves and F9 loads (default snapshot.txt)
+  --keys FILE             key bindings (default keys.cfg if present)
+
+  -h, --help              show this help
+";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Run,
+    Sweep,
+    Webster,
 }
 
-// Function to check if a vehicle is still on screen
-fn is_on_screen(vehicle: &Vehicle) -> bool {
-    let rect = vehicle.rect();
-    match vehicle.direction() {
-        Direction::North => rect.y() > -50, // Allow vehicle to fully leave the screen
-        Direction::South => rect.y() < 850,
-        Direction::East => rect.x() < 850,
-        Direction::West => rect.x() > -50,
+struct Args {
+    help: bool,
+    command: Command,
+    flows: Option<PathBuf>,
+    measured: Option<PathBuf>,
+    saturation: Option<f64>,
+    limits: Limits,
+    vary: Vec<(String, Vec<String>)>,
+    seeds: u32,
+    jobs: Option<usize>,
+    scenario: Option<PathBuf>,
+    seed: Option<u64>,
+    duration: Option<u32>,
+    headless: bool,
+    summary: Option<PathBuf>,
+    speed: Option<f64>,
+    window: Option<(u32, u32)>,
+    export: Option<ExportOptions>,
+    trajectory: Option<PathBuf>,
+    trajectory_every: u32,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    snapshot: PathBuf,
+    keys: Option<PathBuf>,
+}
+
+// Options as listed in `USAGE`.
+// `--export csv,json` turns on result export, `--out DIR` picks where and
+// turns it on too, as CSV unless `--export` says otherwise.
+// `--trajectory FILE` logs every vehicle's state each frame (`.jsonl` for
+// JSON Lines, otherwise CSV), `--trajectory-every N` samples every Nth frame.
+// `--record FILE` saves the seed and inputs on exit, `--replay FILE` plays
+// such a file back. `--snapshot FILE` is where F5 saves and F9 loads.
+// `--keys FILE` reads key bindings, by default from `keys.cfg` if present.
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut options = ExportOptions::default();
+    let mut enabled = false;
+    let mut trajectory = None;
+    let mut trajectory_every = 1;
+    let mut record = None;
+    let mut replay = None;
+    let mut snapshot = PathBuf::from("snapshot.txt");
+    let mut keys = None;
+    let mut help = false;
+    let mut scenario = None;
+    let mut seed = None;
+    let mut duration = None;
+    let mut headless = false;
+    let mut summary = None;
+    let mut speed = None;
+    let mut window = None;
+    let mut vary = Vec::new();
+    let mut seeds = 10;
+    let mut jobs = None;
+    // `run` is the default command, so it may be left out
+    let mut args = args.peekable();
+    let command = match args.next_if(|arg| ["run", "sweep", "webster"].contains(&arg.as_str())) {
+        Some(name) if name == "sweep" => Command::Sweep,
+        Some(name) if name == "webster" => Command::Webster,
+        _ => Command::Run,
+    };
+    let mut flows = None;
+    let mut measured = None;
+    let mut saturation = None;
+    let mut limits = Limits::default();
+    // Seconds or vehicles per hour, greater than zero
+    let amount = |arg: &str, n: String| {
+        n.parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x > 0.0)
+            .ok_or(format!("{} expects a positive number, got '{}'", arg, n))
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--export" => {
+                options.formats = export::parse_formats(&value()?)?;
+                enabled = true;
+            }
+            "--out" => {
+                options.dir = value()?.into();
+                enabled = true;
+            }
+            "--trajectory" => trajectory = Some(value()?.into()),
+            "--trajectory-every" => {
+                let n = value()?;
+                trajectory_every = n.parse().ok().filter(|n| *n > 0).ok_or(format!(
+                    "--trajectory-every expects a positive number, got '{}'",
+                    n
+                ))?;
+            }
+            "--record" => record = Some(value()?.into()),
+            "--replay" => replay = Some(value()?.into()),
+            "--snapshot" => snapshot = value()?.into(),
+            "--keys" => keys = Some(value()?.into()),
+            "--scenario" => scenario = Some(value()?.into()),
+            "--seed" => {
+                let n = value()?;
+                seed = Some(
+                    n.parse()
+                        .map_err(|_| format!("--seed expects a whole number, got '{}'", n))?,
+                );
+            }
+            "--duration" => {
+                let n = value()?;
+                duration = Some(
+                    n.parse()
+                        .ok()
+                        .filter(|n| *n > 0 && simulation::frames(*n).is_some())
+                        .ok_or(format!(
+                            "--duration expects a positive number of seconds up to {}, got '{}'",
+                            i32::MAX as u32 / FRAMES_PER_SECOND,
+                            n
+                        ))?,
+                );
+            }
+            "--headless" => headless = true,
+            "--summary" => summary = Some(value()?.into()),
+            "--speed" => {
+                let n = value()?;
+                let x = n.trim_end_matches('x');
+                speed = Some(
+                    x.parse()
+                        .ok()
+                        .filter(|x| playback::SPEEDS.contains(x))
+                        .ok_or(format!(
+                            "--speed expects one of {:?}, got '{}'",
+                            playback::SPEEDS,
+                            n
+                        ))?,
+                );
+            }
+            "--window" => {
+                let n = value()?;
+                window = Some(
+                    n.split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|(w, h)| *w > 0 && *h > 0)
+                        .ok_or(format!("--window expects WIDTHxHEIGHT, got '{}'", n))?,
+                );
+            }
+            "--flows" => flows = Some(value()?.into()),
+            "--measured" => measured = Some(value()?.into()),
+            "--saturation" => saturation = Some(amount(&arg, value()?)?),
+            "--lost-time" => limits.lost_time = amount(&arg, value()?)?,
+            "--min-cycle" => limits.min_cycle = amount(&arg, value()?)?,
+            "--max-cycle" => limits.max_cycle = amount(&arg, value()?)?,
+            "--vary" => vary.push(sweep::parse_vary(&value()?)?),
+            "--seeds" => {
+                let n = value()?;
+                seeds = n
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(format!("--seeds expects a positive number, got '{}'", n))?;
+            }
+            "--jobs" => {
+                let n = value()?;
+                jobs = Some(
+                    n.parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or(format!("--jobs expects a positive number, got '{}'", n))?,
+                );
+            }
+            "-h" | "--help" => help = true,
+            other => {
+                return Err(format!(
+                    "unknown argument '{}' (see --help for the options)",
+                    other
+                ));
+            }
+        }
     }
+    Ok(Args {
+        help,
+        command,
+        flows,
+        measured,
+        saturation,
+        limits,
+        vary,
+        seeds,
+        jobs,
+        scenario,
+        seed,
+        duration,
+        headless,
+        summary,
+        speed,
+        window,
+        export: enabled.then_some(options),
+        trajectory,
+        trajectory_every,
+        record,
+        replay,
+        snapshot,
+        keys,
+    })
 }
@@ -0,0 +1,621 @@
+use crate::conflict::{ConflictMatrix, MOVEMENT_COUNT, Movement};
+use crate::signal::{PhaseStatus, SignalController};
+use crate::traffic_light::{Indication, LightState};
+use crate::vehicle::{Direction, Turn};
+
+pub const PHASE_COUNT: usize = 8;
+
+// What a phase does when nobody is calling it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recall {
+    None, // served only on a detector call
+    Min,  // always served, for at least its minimum green
+    Max,  // always served, for its full maximum green
+}
+
+// Timing for one of the eight NEMA phases (all values in frames)
+#[derive(Clone, Debug)]
+pub struct NemaPhase {
+    pub movements: Vec<Movement>,
+    pub min_green: u32,
+    pub max_green: u32,
+    pub passage: u32,
+    pub recall: Recall,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    Green,
+    Yellow,
+    Red,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Next {
+    Phase(usize),
+    Barrier,
+}
+
+#[derive(Clone, Debug)]
+struct Ring {
+    // Phase order on each side of the barrier (indices into `phases`)
+    sequence: [Vec<usize>; 2],
+    phase: usize,
+    interval: Interval,
+    timer: u32,
+    gap: u32,
+    next: Option<Next>,
+}
+
+// Dual-ring, barrier-based actuated controller. Each ring runs its phases in
+// sequence; the two rings run concurrently but only cross the barrier together,
+// and a ring never starts a phase that conflicts with the other ring's.
+#[derive(Clone, Debug)]
+pub struct NemaController {
+    phases: Vec<NemaPhase>,
+    rings: [Ring; 2],
+    side: usize,
+    yellow: u32,
+    red_clear: u32,
+    matrix: ConflictMatrix,
+}
+
+impl NemaController {
+    // Standard assignment with N-S as the major street:
+    //   ring 1: φ1 SB left, φ2 NB thru | φ3 WB left, φ4 EB thru
+    //   ring 2: φ5 NB left, φ6 SB thru | φ7 EB left, φ8 WB thru
+    // Opposing lefts cross each other in this layout, so ring 2 lags its lefts.
+    pub fn standard(matrix: ConflictMatrix) -> Result<Self, String> {
+        let left = |approach| NemaPhase {
+            movements: vec![Movement {
+                approach,
+                turn: Turn::Left,
+            }],
+            min_green: 60,
+            max_green: 150,
+            passage: 30,
+            recall: Recall::None,
+        };
+        let through = |approach| NemaPhase {
+            movements: vec![
+                Movement {
+                    approach,
+                    turn: Turn::Straight,
+                },
+                Movement {
+                    approach,
+                    turn: Turn::Right,
+                },
+            ],
+            min_green: 90,
+            max_green: 300,
+            passage: 30,
+            recall: Recall::Min,
+        };
+        let phases = vec![
+            left(Direction::South),
+            through(Direction::North),
+            left(Direction::West),
+            through(Direction::East),
+            left(Direction::North),
+            through(Direction::South),
+            left(Direction::East),
+            through(Direction::West),
+        ];
+        let rings = [[vec![0, 1], vec![2, 3]], [vec![5, 4], vec![7, 6]]];
+        NemaController::new(phases, rings, 30, 15, matrix)
+    }
+
+    // `rings[r][side]` lists phase indices (0 = φ1) in the order ring `r`
+    // serves them on that side of the barrier
+    pub fn new(
+        phases: Vec<NemaPhase>,
+        rings: [[Vec<usize>; 2]; 2],
+        yellow: u32,
+        red_clear: u32,
+        matrix: ConflictMatrix,
+    ) -> Result<Self, String> {
+        if phases.len() != PHASE_COUNT {
+            return Err(format!(
+                "expected {} phases, got {}",
+                PHASE_COUNT,
+                phases.len()
+            ));
+        }
+        for (i, phase) in phases.iter().enumerate() {
+            if !matrix.compatible(&phase.movements) {
+                return Err(format!("φ{} greens conflicting movements", i + 1));
+            }
+            if phase.min_green == 0 || phase.max_green < phase.min_green {
+                return Err(format!("φ{} needs 0 < min green <= max green", i + 1));
+            }
+        }
+        for m in Movement::all() {
+            if !phases.iter().any(|p| p.movements.contains(&m)) {
+                return Err(format!(
+                    "movement {:?} {:?} is in no phase",
+                    m.approach, m.turn
+                ));
+            }
+        }
+        for (r, ring) in rings.iter().enumerate() {
+            if ring.iter().any(|side| side.is_empty()) {
+                return Err(format!("ring {} has an empty barrier side", r + 1));
+            }
+            if ring.iter().flatten().any(|p| *p >= PHASE_COUNT) {
+                return Err(format!("ring {} refers to an unknown phase", r + 1));
+            }
+        }
+        // Both rings open on their first recalled phase of side 0 (normally
+        // the major-street throughs)
+        let open = |sequence: [Vec<usize>; 2]| Ring {
+            phase: sequence[0]
+                .iter()
+                .copied()
+                .find(|p| phases[*p].recall != Recall::None)
+                .unwrap_or(sequence[0][0]),
+            sequence,
+            interval: Interval::Green,
+            timer: 0,
+            gap: 0,
+            next: None,
+        };
+        let [r1, r2] = rings;
+        let (a, b) = (open(r1), open(r2));
+        if !phases_compatible(&matrix, &phases[a.phase], &phases[b.phase]) {
+            return Err("rings open on conflicting phases".to_string());
+        }
+        Ok(NemaController {
+            phases,
+            rings: [a, b],
+            side: 0,
+            yellow,
+            red_clear,
+            matrix,
+        })
+    }
+
+    // Current phase (0 = φ1) and interval of each ring
+    pub fn ring_status(&self) -> [(usize, Interval); 2] {
+        [
+            (self.rings[0].phase, self.rings[0].interval),
+            (self.rings[1].phase, self.rings[1].interval),
+        ]
+    }
+
+    pub fn phases(&self) -> &[NemaPhase] {
+        &self.phases
+    }
+
+    fn called(&self, p: usize, calls: &[bool; MOVEMENT_COUNT]) -> bool {
+        self.phases[p].movements.iter().any(|m| calls[m.index()])
+    }
+
+    fn demand(&self, p: usize, calls: &[bool; MOVEMENT_COUNT]) -> bool {
+        self.phases[p].recall != Recall::None || self.called(p, calls)
+    }
+
+    // Phase whose movements are still green or clearing in ring `r`
+    fn active(&self, r: usize) -> Option<usize> {
+        let ring = &self.rings[r];
+        let clearing = ring.interval == Interval::Red && ring.timer < self.red_clear;
+        (ring.interval != Interval::Red || clearing).then_some(ring.phase)
+    }
+
+    // Most frames before ring `r` changes interval, if that is known. Past
+    // max a green rests until something else is called.
+    fn interval_left(&self, r: usize) -> Option<u32> {
+        let ring = &self.rings[r];
+        let end = match ring.interval {
+            Interval::Green => self.phases[ring.phase].max_green,
+            Interval::Yellow => self.yellow,
+            Interval::Red => self.red_clear,
+        };
+        (ring.timer < end).then(|| end - ring.timer)
+    }
+
+    fn fits_other_ring(&self, r: usize, p: usize) -> bool {
+        self.active(1 - r)
+            .is_none_or(|q| phases_compatible(&self.matrix, &self.phases[p], &self.phases[q]))
+    }
+
+    // Green timing allows this ring to end its phase
+    fn timed_out(&self, r: usize) -> bool {
+        let ring = &self.rings[r];
+        let phase = &self.phases[ring.phase];
+        if ring.timer < phase.min_green {
+            return false;
+        }
+        let maxed = ring.timer >= phase.max_green;
+        match phase.recall {
+            Recall::Max => maxed,
+            _ => maxed || ring.gap >= phase.passage,
+        }
+    }
+
+    fn next_for(&self, r: usize, calls: &[bool; MOVEMENT_COUNT]) -> Next {
+        let ring = &self.rings[r];
+        let sequence = &ring.sequence[self.side];
+        let pos = sequence.iter().position(|p| *p == ring.phase);
+        let rest = pos.map_or(&sequence[..], |i| &sequence[i + 1..]);
+        rest.iter()
+            .find(|p| self.demand(**p, calls))
+            .map_or(Next::Barrier, |p| Next::Phase(*p))
+    }
+
+    fn start_green(&mut self, r: usize, p: usize) {
+        let ring = &mut self.rings[r];
+        ring.phase = p;
+        ring.interval = Interval::Green;
+        ring.timer = 0;
+        ring.gap = 0;
+        ring.next = None;
+    }
+
+    fn cross_barrier(&mut self, calls: &[bool; MOVEMENT_COUNT]) {
+        let other = 1 - self.side;
+        let wanted = |c: &Self, side: usize| {
+            c.rings
+                .iter()
+                .flat_map(|ring| ring.sequence[side].iter())
+                .any(|p| c.demand(*p, calls))
+        };
+        // With nothing waiting across the barrier, cycle back on this side
+        if wanted(self, other) {
+            self.side = other;
+        }
+        let side = self.side;
+        let first = |c: &Self, r: usize| {
+            let sequence = &c.rings[r].sequence[side];
+            sequence
+                .iter()
+                .copied()
+                .find(|p| c.demand(*p, calls))
+                .unwrap_or(*sequence.last().unwrap())
+        };
+        let p1 = first(self, 0);
+        self.start_green(0, p1);
+        // Ring 2 takes its first called phase that can run beside ring 1's,
+        // otherwise it waits in red for its first choice
+        let sequence = self.rings[1].sequence[side].clone();
+        let fits = sequence
+            .iter()
+            .copied()
+            .find(|p| self.demand(*p, calls) && self.fits_other_ring(1, *p));
+        match fits {
+            Some(p2) => self.start_green(1, p2),
+            None => {
+                let p2 = first(self, 1);
+                let red_clear = self.red_clear;
+                let ring = &mut self.rings[1];
+                ring.phase = p2;
+                ring.next = Some(Next::Phase(p2));
+                ring.interval = Interval::Red;
+                ring.timer = red_clear;
+            }
+        }
+    }
+}
+
+impl SignalController for NemaController {
+    fn tick(&mut self, calls: &[bool; MOVEMENT_COUNT]) {
+        for r in 0..2 {
+            let called = self.called(self.rings[r].phase, calls);
+            let ring = &mut self.rings[r];
+            ring.timer += 1;
+            if ring.interval == Interval::Green {
+                ring.gap = if called { 0 } else { ring.gap + 1 };
+            }
+        }
+
+        // Rest in green while nothing else is waiting
+        let serving = [self.rings[0].phase, self.rings[1].phase];
+        let waiting = (0..PHASE_COUNT).any(|p| !serving.contains(&p) && self.demand(p, calls));
+
+        for r in 0..2 {
+            match self.rings[r].interval {
+                Interval::Green => {
+                    if !waiting || !self.timed_out(r) {
+                        continue;
+                    }
+                    let next = self.next_for(r, calls);
+                    let go = match next {
+                        // Keep serving this phase rather than sit in red
+                        // waiting for the other ring to make room
+                        Next::Phase(p) => self.fits_other_ring(r, p),
+                        // Hold green at the barrier until the other ring is
+                        // also ready to cross it
+                        Next::Barrier => {
+                            let o = 1 - r;
+                            self.rings[o].next == Some(Next::Barrier)
+                                || (self.rings[o].interval == Interval::Green
+                                    && self.timed_out(o)
+                                    && self.next_for(o, calls) == Next::Barrier)
+                        }
+                    };
+                    if go {
+                        let ring = &mut self.rings[r];
+                        ring.interval = Interval::Yellow;
+                        ring.timer = 0;
+                        ring.next = Some(next);
+                    }
+                }
+                Interval::Yellow => {
+                    if self.rings[r].timer >= self.yellow {
+                        let ring = &mut self.rings[r];
+                        ring.interval = Interval::Red;
+                        ring.timer = 0;
+                    }
+                }
+                Interval::Red => {
+                    if self.rings[r].timer < self.red_clear {
+                        continue;
+                    }
+                    match self.rings[r].next {
+                        Some(Next::Phase(p)) => {
+                            let o = 1 - r;
+                            if self.fits_other_ring(r, p) {
+                                self.start_green(r, p);
+                            } else if self.rings[o].interval == Interval::Green
+                                && self.timed_out(o)
+                                && self.next_for(o, calls) == Next::Barrier
+                            {
+                                // The other ring is done for this side and
+                                // will never make room; skip to the barrier
+                                self.rings[r].next = Some(Next::Barrier);
+                            }
+                        }
+                        Some(Next::Barrier) => {
+                            let other = &self.rings[1 - r];
+                            if other.next == Some(Next::Barrier)
+                                && other.interval == Interval::Red
+                                && other.timer >= self.red_clear
+                            {
+                                self.cross_barrier(calls);
+                                break;
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_green(&self, movement: Movement) -> bool {
+        self.rings.iter().any(|ring| {
+            ring.interval == Interval::Green
+                && self.phases[ring.phase].movements.contains(&movement)
+        })
+    }
+
+    // [side, then per ring: phase, interval, timer, gap, next]. Intervals are
+    // 0 green, 1 yellow, 2 red; next is 0 none, 1 barrier, 2 + phase.
+    fn state(&self) -> Vec<u32> {
+        let mut state = vec![self.side as u32];
+        for ring in &self.rings {
+            let interval = match ring.interval {
+                Interval::Green => 0,
+                Interval::Yellow => 1,
+                Interval::Red => 2,
+            };
+            let next = match ring.next {
+                None => 0,
+                Some(Next::Barrier) => 1,
+                Some(Next::Phase(p)) => 2 + p as u32,
+            };
+            state.extend([ring.phase as u32, interval, ring.timer, ring.gap, next]);
+        }
+        state
+    }
+
+    fn restore(&mut self, state: &[u32]) -> Result<(), String> {
+        let bad = || "signal state doesn't match this controller".to_string();
+        let [side, rings @ ..] = state else {
+            return Err(bad());
+        };
+        if *side > 1 || rings.len() != 10 {
+            return Err(bad());
+        }
+        let mut restored = self.rings.clone();
+        for (ring, s) in restored.iter_mut().zip(rings.chunks(5)) {
+            let phase = s[0] as usize;
+            let in_ring = |p: usize| ring.sequence.iter().any(|side| side.contains(&p));
+            if !in_ring(phase) {
+                return Err(bad());
+            }
+            ring.phase = phase;
+            ring.interval = match s[1] {
+                0 => Interval::Green,
+                1 => Interval::Yellow,
+                2 => Interval::Red,
+                _ => return Err(bad()),
+            };
+            ring.timer = s[2];
+            ring.gap = s[3];
+            ring.next = match s[4] {
+                0 => None,
+                1 => Some(Next::Barrier),
+                n if in_ring(n as usize - 2) => Some(Next::Phase(n as usize - 2)),
+                _ => return Err(bad()),
+            };
+        }
+        self.side = *side as usize;
+        self.rings = restored;
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn SignalController> {
+        Box::new(self.clone())
+    }
+
+    // Greens count down to max-out; gapping out can end them sooner
+    fn status(&self) -> Vec<PhaseStatus> {
+        (0..2)
+            .map(|r| {
+                let ring = &self.rings[r];
+                PhaseStatus {
+                    name: format!("P{}", ring.phase + 1),
+                    interval: match ring.interval {
+                        Interval::Green => "green",
+                        Interval::Yellow => "yellow",
+                        Interval::Red => "red",
+                    },
+                    remaining: self.interval_left(r),
+                }
+            })
+            .collect()
+    }
+
+    // Heads show green and yellow while a ring serves their phase. How long
+    // a red lasts depends on calls not yet made, so it has no countdown.
+    fn indication(&self, movement: Movement) -> Indication {
+        for (r, ring) in self.rings.iter().enumerate() {
+            if !self.phases[ring.phase].movements.contains(&movement) {
+                continue;
+            }
+            let state = match ring.interval {
+                Interval::Green => LightState::Green,
+                Interval::Yellow => LightState::Yellow,
+                Interval::Red => continue,
+            };
+            return Indication {
+                state,
+                remaining: self.interval_left(r),
+            };
+        }
+        Indication {
+            state: LightState::Red,
+            remaining: None,
+        }
+    }
+}
+
+fn phases_compatible(matrix: &ConflictMatrix, a: &NemaPhase, b: &NemaPhase) -> bool {
+    a.movements
+        .iter()
+        .all(|m| b.movements.iter().all(|n| !matrix.conflicts(*m, *n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> NemaController {
+        NemaController::standard(ConflictMatrix::from_geometry()).unwrap()
+    }
+
+    fn calling(movements: &[Movement]) -> [bool; MOVEMENT_COUNT] {
+        let mut calls = [false; MOVEMENT_COUNT];
+        for m in movements {
+            calls[m.index()] = true;
+        }
+        calls
+    }
+
+    fn side_of(c: &NemaController, r: usize, p: usize) -> usize {
+        c.rings[r]
+            .sequence
+            .iter()
+            .position(|s| s.contains(&p))
+            .unwrap()
+    }
+
+    // Ticks until ring `r` leaves green, calling `calls(tick)` each frame
+    fn green_length(c: &mut NemaController, r: usize, calls: impl Fn(u32) -> Vec<Movement>) -> u32 {
+        let mut ticks = 0;
+        while c.ring_status()[r].1 == Interval::Green {
+            ticks += 1;
+            c.tick(&calling(&calls(ticks)));
+            assert!(ticks < 10_000, "ring {} never left green", r + 1);
+        }
+        ticks
+    }
+
+    #[test]
+    fn rings_cross_the_barrier_together() {
+        let mut c = controller();
+        let all = calling(&Movement::all());
+        for _ in 0..20_000 {
+            c.tick(&all);
+            let [(p1, i1), (p2, i2)] = c.ring_status();
+            if i1 != Interval::Red && i2 != Interval::Red {
+                assert_eq!(side_of(&c, 0, p1), side_of(&c, 1, p2));
+            }
+            for (r, p) in [(0, p1), (1, p2)] {
+                if c.ring_status()[r].1 == Interval::Green {
+                    assert_eq!(side_of(&c, r, p), c.side);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rings_serve_their_phases_lead_lag() {
+        let mut c = controller();
+        let all = calling(&Movement::all());
+        let mut served: [Vec<usize>; 2] = [Vec::new(), Vec::new()];
+        let mut last = [None, None];
+        for _ in 0..20_000 {
+            c.tick(&all);
+            for (r, (p, interval)) in c.ring_status().into_iter().enumerate() {
+                let green = (interval == Interval::Green).then_some(p);
+                if green.is_some() && green != last[r] {
+                    served[r].push(p);
+                }
+                last[r] = green;
+            }
+        }
+        // Opening on the throughs, then [[φ1, φ2], [φ3, φ4]] and [[φ6, φ5], [φ8, φ7]]
+        assert_eq!(served[0][..9], [1, 2, 3, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(served[1][..9], [5, 4, 7, 6, 5, 4, 7, 6, 5]);
+    }
+
+    #[test]
+    fn greens_gap_out_without_calls_and_max_out_with_them() {
+        let south_through = Movement {
+            approach: Direction::South,
+            turn: Turn::Straight,
+        };
+        // φ6 has 90 frames of min green, 300 of max and 30 of passage
+        assert_eq!(green_length(&mut controller(), 1, |_| vec![]), 90);
+        assert_eq!(
+            green_length(&mut controller(), 1, |_| vec![south_through]),
+            300
+        );
+        let until = |last: u32| {
+            move |tick: u32| match tick <= last {
+                true => vec![south_through],
+                false => vec![],
+            }
+        };
+        assert_eq!(green_length(&mut controller(), 1, until(120)), 150);
+        assert_eq!(green_length(&mut controller(), 1, until(40)), 90);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut c = controller();
+        let some = calling(&Movement::all()[..7]);
+        for _ in 0..1234 {
+            c.tick(&some);
+        }
+        let mut restored = controller();
+        restored.restore(&c.state()).unwrap();
+        assert_eq!(restored.state(), c.state());
+        for _ in 0..2000 {
+            c.tick(&some);
+            restored.tick(&some);
+            assert_eq!(restored.state(), c.state());
+        }
+
+        // [side, ring 1 phase, interval, timer, gap, next, ring 2 phase, ...]
+        let mut bad = c.state();
+        bad[6] = 0; // φ1 isn't in ring 2
+        assert!(controller().restore(&bad).is_err());
+        let mut bad = c.state();
+        bad[2] = 3; // no such interval
+        assert!(controller().restore(&bad).is_err());
+        assert!(controller().restore(&c.state()[..10]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+// Speeds the viewer can run at, as simulation steps per rendered frame
+pub const SPEEDS: [f64; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+const NORMAL: usize = 2;
+
+// Pause, single-step and speed for the viewer. Speeds above 1× run several
+// simulation steps per rendered frame, speeds below it skip frames; the
+// simulation itself always advances in whole frames.
+pub struct Playback {
+    pub paused: bool,
+    speed: usize,       // index into SPEEDS
+    owed: f64,          // fraction of a step carried over to the next frame
+    step_pending: bool, // one step requested while paused
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            paused: false,
+            speed: NORMAL,
+            owed: 0.0,
+            step_pending: false,
+        }
+    }
+}
+
+impl Playback {
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.owed = 0.0;
+    }
+
+    // Advance exactly one step; only does anything while paused
+    pub fn step_once(&mut self) {
+        if self.paused {
+            self.step_pending = true;
+        }
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    // Run at the one of `SPEEDS` nearest to `speed`
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = (0..SPEEDS.len())
+            .min_by(|a, b| {
+                (SPEEDS[*a] - speed)
+                    .abs()
+                    .total_cmp(&(SPEEDS[*b] - speed).abs())
+            })
+            .unwrap_or(NORMAL);
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    // Simulation steps to run for this rendered frame
+    pub fn steps(&mut self) -> u32 {
+        if self.paused {
+            return std::mem::take(&mut self.step_pending) as u32;
+        }
+        self.owed += self.speed();
+        let steps = self.owed.floor();
+        self.owed -= steps;
+        steps as u32
+    }
+
+    // "Paused" or the speed, e.g. "0.25x"
+    pub fn label(&self) -> String {
+        if self.paused {
+            "Paused".to_string()
+        } else {
+            format!("{}x", self.speed())
+        }
+    }
+
+    // Mode indicator in the top-left corner: pause bars or a play triangle,
+    // then one pip per speed with the current one and those below it lit
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        let (x, y) = (10, 10);
+        canvas.set_draw_color(Color::RGB(30, 30, 30));
+        let _ = canvas.fill_rect(Rect::new(x - 4, y - 4, 32 + 10 * SPEEDS.len() as u32, 24));
+        canvas.set_draw_color(Color::WHITE);
+        if self.paused {
+            let _ = canvas.fill_rect(Rect::new(x, y, 5, 16));
+            let _ = canvas.fill_rect(Rect::new(x + 9, y, 5, 16));
+        } else {
+            for dx in 0..14 {
+                let half = 8 - dx * 8 / 14;
+                let _ = canvas.draw_line(
+                    Point::new(x + dx, y + 8 - half),
+                    Point::new(x + dx, y + 8 + half),
+                );
+            }
+        }
+        for i in 0..SPEEDS.len() {
+            let lit = i <= self.speed;
+            canvas.set_draw_color(match (lit, i == NORMAL) {
+                (true, true) => Color::RGB(0, 200, 0),
+                (true, false) => Color::WHITE,
+                (false, _) => Color::RGB(80, 80, 80),
+            });
+            let _ = canvas.fill_rect(Rect::new(x + 24 + 10 * i as i32, y + 4, 6, 8));
+        }
+    }
+}
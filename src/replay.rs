@@ -0,0 +1,317 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::conflict::Movement;
+use crate::export::{
+    direction_from_name, direction_name, light_state_from_name, light_state_name, turn_from_name,
+    turn_name,
+};
+use crate::scenario::Scenario;
+use crate::simulation::Simulation;
+use crate::traffic_light::LightState;
+use crate::vehicle::Direction;
+
+// Bumped whenever the session file layout or the meaning of a line changes
+pub const SESSION_VERSION: u32 = 2;
+
+// Something the user did that changes the simulation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Spawn(Direction),
+    SpawnRandom,
+    // Force a movement's signal, or hand it back to the controller with None
+    Override(Movement, Option<LightState>),
+}
+
+impl Input {
+    // Feed this input to `sim`; returns whether a vehicle was added
+    pub fn apply(self, sim: &mut Simulation) -> bool {
+        match self {
+            Input::Spawn(direction) => sim.try_spawn(direction),
+            Input::SpawnRandom => sim.try_spawn_random(),
+            Input::Override(movement, state) => {
+                sim.set_override(movement, state);
+                false
+            }
+        }
+    }
+}
+
+// An input and the frame it was applied on, before that frame's step
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: i32,
+    pub input: Input,
+}
+
+// A recorded run: the seed, the scenario's fingerprint and every input in
+// order. Replaying the inputs on a simulation built with the same seed and
+// scenario reproduces the run exactly. Saved as plain text, one item per
+// line:
+//
+//   road-intersection-session 2
+//   seed 1234
+//   scenario 5d0f6a1c29be7403
+//   41 spawn north
+//   97 spawn random
+//   120 override north left green
+//   300 override north left auto
+//   end 3600
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub seed: u64,
+    // `Scenario::fingerprint` of the run; None from a version 1 file, which
+    // didn't keep it
+    pub scenario: Option<u64>,
+    pub events: Vec<InputEvent>,
+    pub end_frame: i32, // first frame not simulated
+}
+
+impl Session {
+    pub fn new(seed: u64, scenario: &Scenario) -> Self {
+        Session {
+            seed,
+            scenario: Some(scenario.fingerprint()),
+            events: Vec::new(),
+            end_frame: 1,
+        }
+    }
+
+    pub fn record(&mut self, frame: i32, input: Input) {
+        self.events.push(InputEvent { frame, input });
+    }
+
+    // Refuse to replay under a scenario other than the recorded one
+    pub fn check(&self, scenario: &Scenario) -> Result<(), String> {
+        match self.scenario {
+            Some(f) if f != scenario.fingerprint() => Err(
+                "the session was recorded under a different scenario; replay it with the same --scenario"
+                    .to_string(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // Forget inputs from `frame` on, after the run was rewound to it
+    pub fn truncate(&mut self, frame: i32) {
+        self.events.retain(|e| e.frame < frame);
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Session::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!(
+            "road-intersection-session {}\nseed {}\n",
+            SESSION_VERSION, self.seed
+        );
+        if let Some(f) = self.scenario {
+            out.push_str(&format!("scenario {:016x}\n", f));
+        }
+        for e in &self.events {
+            let input = match e.input {
+                Input::Spawn(direction) => format!("spawn {}", direction_name(direction)),
+                Input::SpawnRandom => "spawn random".to_string(),
+                Input::Override(m, state) => format!(
+                    "override {} {} {}",
+                    direction_name(m.approach),
+                    turn_name(m.turn),
+                    state.map_or("auto", light_state_name)
+                ),
+            };
+            out.push_str(&format!("{} {}\n", e.frame, input));
+        }
+        out.push_str(&format!("end {}\n", self.end_frame));
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty());
+        // Version 1 is the same without the scenario line
+        let version = match lines.next() {
+            Some((_, header)) => header
+                .strip_prefix("road-intersection-session ")
+                .and_then(|v| v.parse::<u32>().ok())
+                .filter(|v| (1..=SESSION_VERSION).contains(v)),
+            None => None,
+        };
+        if version.is_none() {
+            return Err(format!("not a version {} session file", SESSION_VERSION));
+        }
+        let mut seed = None;
+        let mut scenario = None;
+        let mut end_frame = None;
+        let mut events: Vec<InputEvent> = Vec::new();
+        for (n, line) in lines {
+            let bad = || format!("line {}: can't read '{}'", n, line);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["seed", value] => seed = Some(value.parse().map_err(|_| bad())?),
+                ["scenario", value] if version == Some(2) => {
+                    scenario = Some(u64::from_str_radix(value, 16).map_err(|_| bad())?)
+                }
+                ["end", value] => end_frame = Some(value.parse().map_err(|_| bad())?),
+                [frame, kind, rest @ ..] => {
+                    let frame: i32 = frame.parse().map_err(|_| bad())?;
+                    let input = match (*kind, rest) {
+                        ("spawn", ["random"]) => Input::SpawnRandom,
+                        ("spawn", [name]) => {
+                            Input::Spawn(direction_from_name(name).ok_or_else(bad)?)
+                        }
+                        ("override", [approach, turn, state]) => {
+                            let movement = Movement {
+                                approach: direction_from_name(approach).ok_or_else(bad)?,
+                                turn: turn_from_name(turn).ok_or_else(bad)?,
+                            };
+                            let state = match *state {
+                                "auto" => None,
+                                name => Some(light_state_from_name(name).ok_or_else(bad)?),
+                            };
+                            Input::Override(movement, state)
+                        }
+                        _ => return Err(bad()),
+                    };
+                    if events.last().is_some_and(|e| e.frame > frame) {
+                        return Err(format!("line {}: frames out of order", n));
+                    }
+                    events.push(InputEvent { frame, input });
+                }
+                _ => return Err(bad()),
+            }
+        }
+        let seed = seed.ok_or("missing seed line")?;
+        let end_frame = end_frame.unwrap_or(events.last().map_or(1, |e| e.frame + 1));
+        if version == Some(2) && scenario.is_none() {
+            return Err("missing scenario line".to_string());
+        }
+        Ok(Session {
+            seed,
+            scenario,
+            events,
+            end_frame,
+        })
+    }
+}
+
+// Plays a session back into a simulation built with its seed. Seeking
+// forward steps quickly to the target; seeking back starts again from a
+// fresh simulation, which is cheap because the run is deterministic.
+pub struct Player {
+    session: Session,
+    next: usize, // index of the first event not yet applied
+}
+
+impl Player {
+    pub fn new(session: Session) -> Self {
+        Player { session, next: 0 }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn finished(&self, sim: &Simulation) -> bool {
+        sim.frame() >= self.session.end_frame
+    }
+
+    // Apply this frame's inputs and advance one frame, unless the recording
+    // has ended
+    pub fn step(&mut self, sim: &mut Simulation) {
+        if self.finished(sim) {
+            return;
+        }
+        while let Some(e) = self.session.events.get(self.next) {
+            if e.frame > sim.frame() {
+                break;
+            }
+            e.input.apply(sim);
+            self.next += 1;
+        }
+        sim.step();
+    }
+
+    // Move to `frame` (clamped to the recording). `fresh` builds a new
+    // simulation with the session's seed for seeking backwards.
+    pub fn seek(&mut self, sim: &mut Simulation, frame: i32, fresh: impl FnOnce() -> Simulation) {
+        let frame = frame.clamp(1, self.session.end_frame);
+        if frame < sim.frame() {
+            *sim = fresh();
+            self.next = 0;
+        }
+        while sim.frame() < frame {
+            self.step(sim);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vehicle::Turn;
+    use std::path::Path;
+
+    fn rush_hour() -> Scenario {
+        Scenario::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/rush-hour.toml"))
+            .unwrap()
+    }
+
+    #[test]
+    fn replaying_a_recording_reproduces_the_run() {
+        let scenario = rush_hour();
+        let mut sim = scenario.simulation(5).unwrap();
+        let mut session = Session::new(sim.seed(), &scenario);
+        let left = Movement {
+            approach: Direction::North,
+            turn: Turn::Left,
+        };
+        let inputs = [
+            (30, Input::Spawn(Direction::West)),
+            (200, Input::SpawnRandom),
+            (400, Input::Override(left, Some(LightState::Green))),
+            (900, Input::Override(left, None)),
+        ];
+        for frame in 1..3000 {
+            for (_, input) in inputs.iter().filter(|(f, _)| *f == frame) {
+                session.record(sim.frame(), *input);
+                input.apply(&mut sim);
+            }
+            sim.step();
+        }
+        session.end_frame = sim.frame();
+
+        let session = Session::parse(&session.to_text()).unwrap();
+        session.check(&scenario).unwrap();
+        let mut replayed = scenario.simulation(session.seed).unwrap();
+        let mut player = Player::new(session);
+        while !player.finished(&replayed) {
+            player.step(&mut replayed);
+        }
+        assert_eq!(replayed.snapshot(), sim.snapshot());
+    }
+
+    #[test]
+    fn a_session_refuses_another_scenario() {
+        let scenario = rush_hour();
+        let session = Session::parse(&Session::new(1, &scenario).to_text()).unwrap();
+        assert!(session.check(&scenario).is_ok());
+        let mut busier = scenario.clone();
+        busier.set("demand.scale", "1.5").unwrap();
+        assert!(session.check(&busier).is_err());
+        assert!(session.check(&Scenario::default()).is_err());
+
+        // Version 1 files have nothing to check against
+        let old = Session::parse("road-intersection-session 1\nseed 1\nend 10\n").unwrap();
+        assert!(old.check(&busier).is_ok());
+    }
+}
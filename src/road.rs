@@ -1,5 +1,5 @@
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use sdl2::rect::{Point, Rect};
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
@@ -25,6 +25,12 @@ impl Road {
             vertical: false,
         }
     }
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+    pub fn contains(&self, p: Point) -> bool {
+        self.rect.contains_point(p)
+    }
     pub fn draw(&self, canvas: &mut Canvas<Window>) {
         canvas.set_draw_color(self.color);
         let _ = canvas.fill_rect(self.rect);
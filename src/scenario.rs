@@ -0,0 +1,423 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::conflict::ConflictMatrix;
+use crate::export::direction_name;
+use crate::nema::NemaController;
+use crate::signal::{FixedTimeController, SignalController, SignalPlan};
+use crate::simulation::{self, FRAMES_PER_SECOND, SAFE_DISTANCE, Simulation};
+use crate::stats::APPROACHES;
+use crate::watchdog::Resolution;
+use crate::webster;
+
+// Which controller runs the lights
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controller {
+    Nema,
+    // The minimal generated plan
+    Fixed,
+}
+
+// Timing for the fixed-time controller: the phases of a loaded `plan` or
+// the generated one, with either `green` seconds for every phase, or a
+// `cycle` of seconds shared out between the phases in proportion to
+// `splits` (equally, or as in the loaded plan, without them)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signals {
+    pub controller: Controller,
+    pub plan: Option<SignalPlan>,
+    pub green: Option<u32>,
+    pub cycle: Option<u32>,
+    pub splits: Option<Vec<f64>>,
+}
+
+// How a run is set up: everything but the user's input. Read from a small
+// subset of TOML (`key = value` lines under `[section]` headers, `#`
+// comments), e.g.
+//
+//   seed = 42
+//   duration = 3600        # seconds
+//
+//   [demand]               # vehicles per hour entering from each side
+//   north = 600            # northbound, i.e. entering at the bottom
+//   south = 600
+//   east = 300
+//   west = 300
+//   scale = 1.0            # multiplies all four
+//
+//   [vehicles]
+//   safe_distance = 50     # pixels kept to the vehicle ahead
+//
+//   [signals]
+//   controller = "fixed"   # or "nema"
+//   green = 12             # seconds per phase (default 10), fixed time only
+//   # or instead of green:
+//   # cycle = 60
+//   # splits = [2, 1, 1, 1]
+//   # or a plan file, e.g. from the Webster optimiser:
+//   # plan = "plan.txt"      # relative to this file
+//   saturation = 1900      # veh/h of green per lane (default), for the
+//                          # level of service and the Webster optimiser
+//
+//   [watchdog]
+//   stuck_after = 20       # seconds still before a vehicle counts as stuck
+//   resolution = "report"  # or "remove" a vehicle from each deadlock
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub seed: Option<u64>,
+    pub duration: Option<u32>, // seconds
+    pub demand: [f64; 4],      // vehicles per hour, in `APPROACHES` order
+    pub demand_scale: f64,
+    pub safe_distance: i32,
+    // Vehicles per hour of green per lane, for the level of service and the
+    // Webster optimiser
+    pub saturation: f64,
+    pub signals: Signals,
+    // Seconds still before a vehicle is stuck; None for the watchdog's
+    // default, raised to a full cycle of a fixed plan
+    pub stuck_after: Option<u32>,
+    pub resolution: Resolution,
+    // Where relative paths in the file start from
+    dir: PathBuf,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            seed: None,
+            duration: None,
+            demand: [0.0; 4],
+            demand_scale: 1.0,
+            safe_distance: SAFE_DISTANCE,
+            saturation: webster::SATURATION,
+            signals: Signals {
+                controller: Controller::Nema,
+                plan: None,
+                green: None,
+                cycle: None,
+                splits: None,
+            },
+            stuck_after: None,
+            resolution: Resolution::Report,
+            dir: PathBuf::new(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Scenario::parse_in(&text, dir).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Scenario::parse_in(text, PathBuf::new())
+    }
+
+    // Parse with relative paths starting from `dir`
+    fn parse_in(text: &str, dir: PathBuf) -> Result<Self, String> {
+        let mut scenario = Scenario {
+            dir,
+            ..Scenario::default()
+        };
+        for entry in entries(text)? {
+            let name = match entry.section.as_str() {
+                "" => entry.key,
+                section => format!("{}.{}", section, entry.key),
+            };
+            scenario
+                .set(&name, &entry.value)
+                .map_err(|e| format!("line {}: {}", entry.line, e))?;
+        }
+        scenario.plan()?;
+        Ok(scenario)
+    }
+
+    // Change one setting, named `section.key` (just `key` at the top) with
+    // its value written as in the file. Signal timings replace the ones they
+    // compete with and select the fixed controller, so a sweep can vary the
+    // cycle of a scenario timed by `green`, or of one without signals.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let bad = |what: &str| format!("{} expects {}, got '{}'", name, what, value);
+        match name.split_once('.').unwrap_or(("", name)) {
+            ("", "seed") => self.seed = Some(value.parse().map_err(|_| bad("a whole number"))?),
+            ("", "duration") => self.duration = Some(seconds(value).ok_or_else(|| bad(SECONDS))?),
+            ("demand", "scale") => {
+                self.demand_scale = rate(value).ok_or_else(|| bad("a factor of 0 or more"))?
+            }
+            ("demand", approach) => {
+                let k = APPROACHES
+                    .iter()
+                    .position(|d| direction_name(*d) == approach)
+                    .ok_or_else(|| format!("no approach '{}'", approach))?;
+                self.demand[k] = rate(value).ok_or_else(|| bad("vehicles per hour"))?;
+            }
+            ("vehicles", "safe_distance") => {
+                self.safe_distance = value
+                    .parse()
+                    .ok()
+                    .filter(|d| *d > 0)
+                    .ok_or_else(|| bad("a positive number of pixels"))?
+            }
+            ("signals", "saturation") => {
+                self.saturation = rate(value)
+                    .filter(|s| *s > 0.0)
+                    .ok_or_else(|| bad("vehicles per hour of green"))?
+            }
+            ("signals", "controller") => {
+                self.signals.controller = match string(value).as_deref() {
+                    Some("nema") => Controller::Nema,
+                    Some("fixed") => Controller::Fixed,
+                    _ => return Err(bad("\"nema\" or \"fixed\"")),
+                }
+            }
+            ("signals", "plan") => {
+                let path = string(value).ok_or_else(|| bad("a quoted file name"))?;
+                self.signals.plan = Some(SignalPlan::load(&self.dir.join(path))?);
+                self.signals.controller = Controller::Fixed;
+            }
+            ("signals", "green") => {
+                self.signals.green = Some(seconds(value).ok_or_else(|| bad(SECONDS))?);
+                self.signals.cycle = None;
+                self.signals.splits = None;
+                self.signals.controller = Controller::Fixed;
+            }
+            ("signals", "cycle") => {
+                self.signals.cycle = Some(seconds(value).ok_or_else(|| bad(SECONDS))?);
+                self.signals.green = None;
+                self.signals.controller = Controller::Fixed;
+            }
+            ("signals", "splits") => {
+                self.signals.green = None;
+                self.signals.controller = Controller::Fixed;
+                self.signals.splits = Some(
+                    array(value)
+                        .and_then(|items| {
+                            items.iter().map(|s| rate(s).filter(|w| *w > 0.0)).collect()
+                        })
+                        .ok_or_else(|| bad("a list of positive weights such as [2, 1, 1]"))?,
+                )
+            }
+            ("watchdog", "stuck_after") => {
+                self.stuck_after = Some(seconds(value).ok_or_else(|| bad(SECONDS))?)
+            }
+            ("watchdog", "resolution") => {
+                self.resolution = match string(value).as_deref() {
+                    Some("report") => Resolution::Report,
+                    Some("remove") => Resolution::RemoveOne,
+                    _ => return Err(bad("\"report\" or \"remove\"")),
+                }
+            }
+            _ => return Err(format!("unknown setting '{}'", name)),
+        }
+        Ok(())
+    }
+
+    // Arrival rates with the scale applied, in `APPROACHES` order
+    pub fn rates(&self) -> [f64; 4] {
+        self.demand.map(|rate| rate * self.demand_scale)
+    }
+
+    // The fixed-time plan, or None under the NEMA controller
+    pub fn plan(&self) -> Result<Option<SignalPlan>, String> {
+        let signals = &self.signals;
+        let timed = signals.green.is_some() || signals.cycle.is_some() || signals.splits.is_some();
+        if signals.controller == Controller::Nema {
+            if timed || signals.plan.is_some() {
+                return Err(
+                    "signals.plan, green, cycle and splits only apply to the fixed controller"
+                        .to_string(),
+                );
+            }
+            return Ok(None);
+        }
+        let matrix = ConflictMatrix::from_geometry();
+        let mut plan = match &signals.plan {
+            Some(plan) => plan.clone(),
+            None => SignalPlan::generate(&matrix, 10 * FRAMES_PER_SECOND),
+        };
+        let phases = plan.phases.len();
+        let greens: Option<Vec<u32>> = match (signals.green, signals.cycle, &signals.splits) {
+            (Some(_), Some(_), _) => {
+                return Err("signals.green and signals.cycle can't both be set".to_string());
+            }
+            (_, None, Some(_)) => return Err("signals.splits needs signals.cycle".to_string()),
+            (Some(green), None, None) => Some(vec![green * FRAMES_PER_SECOND; phases]),
+            (None, None, None) => None,
+            (None, Some(cycle), splits) => {
+                let weights = match splits {
+                    Some(weights) => weights.clone(),
+                    None => plan.phases.iter().map(|p| p.green as f64).collect(),
+                };
+                if weights.len() != phases {
+                    return Err(format!(
+                        "signals.splits has {} weights but the plan has {} phases",
+                        weights.len(),
+                        phases
+                    ));
+                }
+                Some(split(cycle * FRAMES_PER_SECOND, &weights))
+            }
+        };
+        for (phase, green) in plan.phases.iter_mut().zip(greens.into_iter().flatten()) {
+            phase.green = green;
+        }
+        plan.validate(&matrix)?;
+        Ok(Some(plan))
+    }
+
+    // A fresh controller as the scenario describes, checked against the
+    // movement conflicts
+    pub fn controller(&self) -> Result<Box<dyn SignalController>, String> {
+        Ok(match self.plan()? {
+            None => Box::new(NemaController::standard(ConflictMatrix::from_geometry())?),
+            Some(plan) => Box::new(FixedTimeController::new(plan)),
+        })
+    }
+
+    // The rest of the setup on a simulation running `controller()`: demand,
+    // following distance, and the watchdog's policy. Unless told otherwise
+    // it won't take a long red for a stuck vehicle.
+    pub fn set_up(&self, sim: &mut Simulation) {
+        sim.set_demand(self.rates());
+        sim.set_safe_distance(self.safe_distance);
+        let config = &mut sim.watchdog.config;
+        config.resolution = self.resolution;
+        match self.stuck_after {
+            Some(seconds) => config.stuck_after = seconds * FRAMES_PER_SECOND,
+            None => {
+                if let Ok(Some(plan)) = self.plan() {
+                    config.stuck_after = config.stuck_after.max(plan.cycle_length());
+                }
+            }
+        }
+    }
+
+    // A hash of everything here that shapes a run, i.e. all but the seed and
+    // length, so a recorded session can tell it's replayed under another
+    pub fn fingerprint(&self) -> u64 {
+        let plan = self.plan().ok().flatten().map(|plan| plan.to_text());
+        let text = format!(
+            "{:?} {} {} {:?} {:?} {:?} {:?}",
+            self.rates(),
+            self.safe_distance,
+            self.saturation,
+            self.signals.controller,
+            plan,
+            self.stuck_after,
+            self.resolution
+        );
+        // FNV-1a, which unlike std's hasher is the same on every build
+        text.bytes().fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    // A simulation set up as described, with this seed
+    pub fn simulation(&self, seed: u64) -> Result<Simulation, String> {
+        let mut sim = Simulation::with_seed(self.controller()?, seed);
+        self.set_up(&mut sim);
+        Ok(sim)
+    }
+}
+
+// `total` frames shared out in proportion to `weights`, rounding so the
+// shares add up to `total`, none shorter than a frame
+fn split(total: u32, weights: &[f64]) -> Vec<u32> {
+    let sum: f64 = weights.iter().sum();
+    let (mut given, mut before) = (0.0, 0);
+    weights
+        .iter()
+        .map(|w| {
+            given += w;
+            let upto = (total as f64 * given / sum).round() as u32;
+            let share = upto.saturating_sub(before).max(1);
+            before = upto;
+            share
+        })
+        .collect()
+}
+
+// One `key = value` line and the section it's in ("" before any header)
+struct Entry {
+    line: usize,
+    section: String,
+    key: String,
+    value: String,
+}
+
+fn entries(text: &str) -> Result<Vec<Entry>, String> {
+    let mut section = String::new();
+    let mut out = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected 'key = value'", n + 1))?;
+        out.push(Entry {
+            line: n + 1,
+            section: section.clone(),
+            key: key.trim().to_string(),
+            value: value.trim().to_string(),
+        });
+    }
+    Ok(out)
+}
+
+// Everything before a `#` that isn't inside a quoted string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn string(value: &str) -> Option<String> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .map(str::to_string)
+}
+
+// Items of a `[a, b, c]` list
+fn array(value: &str) -> Option<Vec<String>> {
+    let inner = value.strip_prefix('[')?.strip_suffix(']')?;
+    Some(
+        inner
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+const SECONDS: &str = "a positive number of seconds, not too many to count in frames";
+
+// A length of time that fits in a frame count
+fn seconds(value: &str) -> Option<u32> {
+    value
+        .parse()
+        .ok()
+        .filter(|s| *s > 0 && simulation::frames(*s).is_some())
+}
+
+fn rate(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+}
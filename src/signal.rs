@@ -0,0 +1,417 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::conflict::{ConflictMatrix, MOVEMENT_COUNT, Movement};
+use crate::export::{direction_from_name, direction_name, turn_from_name, turn_name};
+use crate::traffic_light::{Indication, LightState};
+use crate::vehicle::{Direction, Turn, Vehicle};
+
+// Bumped whenever the plan file layout or the meaning of a line changes
+pub const PLAN_VERSION: u32 = 1;
+
+// How far back from the stop line a waiting or approaching vehicle places a call
+const DETECTOR_LENGTH: i32 = 150;
+
+// Anything that decides which movements may go. Controllers are ticked once
+// per frame with the current detector calls (one flag per movement index).
+pub trait SignalController {
+    fn tick(&mut self, calls: &[bool; MOVEMENT_COUNT]);
+    fn is_green(&self, movement: Movement) -> bool;
+    // Intervals and timers as plain numbers, for snapshots. The plan and
+    // timings are not included: `restore` expects a controller built the same
+    // way and rejects state that doesn't fit it.
+    fn state(&self) -> Vec<u32>;
+    fn restore(&mut self, state: &[u32]) -> Result<(), String>;
+    // A copy behind a new box, so simulations can be cloned for rewinding
+    fn clone_box(&self) -> Box<dyn SignalController>;
+    // What each ring (or the plan) is showing right now, for display
+    fn status(&self) -> Vec<PhaseStatus>;
+    // What a head for `movement` shows, with its countdown. Vehicles only
+    // ever go by `is_green`.
+    fn indication(&self, movement: Movement) -> Indication;
+}
+
+// One ring's current phase and interval. `remaining` is the most frames left
+// before it changes (actuated greens may end sooner), None while it waits
+// with no set end.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhaseStatus {
+    pub name: String,
+    pub interval: &'static str,
+    pub remaining: Option<u32>,
+}
+
+impl Clone for Box<dyn SignalController> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+// Movements with a vehicle on their approach detector, i.e. not yet in the
+// box and within `DETECTOR_LENGTH` of the stop line
+pub fn detector_calls(vehicles: &[Vehicle]) -> [bool; MOVEMENT_COUNT] {
+    let mut calls = [false; MOVEMENT_COUNT];
+    for v in vehicles {
+        if v.in_intersection || v.has_turned {
+            continue;
+        }
+        let m = v.movement();
+        if m.progress(v.rect().center()) >= -DETECTOR_LENGTH {
+            calls[m.index()] = true;
+        }
+    }
+    calls
+}
+
+// One stage of a fixed-time plan: the movements that run green together and
+// for how many frames
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Phase {
+    pub movements: Vec<Movement>,
+    pub green: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignalPlan {
+    pub phases: Vec<Phase>,
+}
+
+impl SignalPlan {
+    // One approach at a time, all of its turns together: N, E, S, W heads
+    pub fn round_robin(green: u32) -> Self {
+        let phases = [
+            Direction::South, // north head
+            Direction::West,  // east head
+            Direction::North, // south head
+            Direction::East,  // west head
+        ]
+        .into_iter()
+        .map(|approach| Phase {
+            movements: [Turn::Straight, Turn::Right, Turn::Left]
+                .into_iter()
+                .map(|turn| Movement { approach, turn })
+                .collect(),
+            green,
+        })
+        .collect();
+        SignalPlan { phases }
+    }
+
+    // Smallest set of phases that serves every movement without greening two
+    // conflicting ones together. Each phase is then widened with any movement
+    // that fits, so non-conflicting movements pick up extra green.
+    pub fn generate(matrix: &ConflictMatrix, green: u32) -> Self {
+        let movements = Movement::all();
+        let mut colors = vec![0usize; MOVEMENT_COUNT];
+        let mut k = 1;
+        while !color(matrix, &movements, &mut colors, 0, k) {
+            k += 1;
+        }
+
+        let phases = (0..k)
+            .map(|c| {
+                let mut members: Vec<Movement> = movements
+                    .iter()
+                    .filter(|m| colors[m.index()] == c)
+                    .copied()
+                    .collect();
+                for m in &movements {
+                    if !members.contains(m)
+                        && members.iter().all(|other| !matrix.conflicts(*m, *other))
+                    {
+                        members.push(*m);
+                    }
+                }
+                members.sort_by_key(|m| m.index());
+                Phase {
+                    movements: members,
+                    green,
+                }
+            })
+            .collect();
+        SignalPlan { phases }
+    }
+
+    // Reject plans that are empty, have zero-length phases, a cycle too long
+    // to count in frames, green two conflicting movements together or never
+    // serve some movement
+    pub fn validate(&self, matrix: &ConflictMatrix) -> Result<(), String> {
+        if self.phases.is_empty() {
+            return Err("signal plan has no phases".to_string());
+        }
+        let cycle = self
+            .phases
+            .iter()
+            .try_fold(0u32, |total, p| total.checked_add(p.green));
+        if cycle.and_then(|c| i32::try_from(c).ok()).is_none() {
+            return Err("signal plan's cycle is too long".to_string());
+        }
+        for (i, phase) in self.phases.iter().enumerate() {
+            if phase.green == 0 {
+                return Err(format!("phase {} has zero green time", i + 1));
+            }
+            for (k, a) in phase.movements.iter().enumerate() {
+                for b in &phase.movements[k + 1..] {
+                    if matrix.conflicts(*a, *b) {
+                        return Err(format!(
+                            "phase {} greens conflicting movements {:?} {:?} and {:?} {:?}",
+                            i + 1,
+                            a.approach,
+                            a.turn,
+                            b.approach,
+                            b.turn
+                        ));
+                    }
+                }
+            }
+        }
+        for m in Movement::all() {
+            if !self.phases.iter().any(|p| p.movements.contains(&m)) {
+                return Err(format!(
+                    "movement {:?} {:?} never gets green",
+                    m.approach, m.turn
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cycle_length(&self) -> u32 {
+        self.phases.iter().map(|p| p.green).sum()
+    }
+
+    // Plan file: a version line, then one line per phase in order with its
+    // green in frames and the movements it serves; `#` starts a comment.
+    //
+    //   road-intersection-plan 1
+    //   phase 1020 north straight, north right, north left, east right
+    pub fn to_text(&self) -> String {
+        let mut out = format!("road-intersection-plan {}\n", PLAN_VERSION);
+        for phase in &self.phases {
+            let movements: Vec<String> = phase
+                .movements
+                .iter()
+                .map(|m| format!("{} {}", direction_name(m.approach), turn_name(m.turn)))
+                .collect();
+            out.push_str(&format!("phase {} {}\n", phase.green, movements.join(", ")));
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.split('#').next().unwrap_or("").trim()))
+            .filter(|(_, l)| !l.is_empty());
+        match lines.next() {
+            Some((_, header)) if header == format!("road-intersection-plan {}", PLAN_VERSION) => {}
+            _ => return Err(format!("not a version {} plan file", PLAN_VERSION)),
+        }
+        let mut phases = Vec::new();
+        for (n, line) in lines {
+            let bad = || format!("line {}: can't read '{}'", n, line);
+            let rest = line.strip_prefix("phase ").ok_or_else(bad)?.trim_start();
+            let (green, list) = rest.split_once(' ').ok_or_else(bad)?;
+            let movements = list
+                .split(',')
+                .map(
+                    |m| match m.split_whitespace().collect::<Vec<_>>().as_slice() {
+                        [approach, turn] => Some(Movement {
+                            approach: direction_from_name(approach)?,
+                            turn: turn_from_name(turn)?,
+                        }),
+                        _ => None,
+                    },
+                )
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(bad)?;
+            phases.push(Phase {
+                movements,
+                green: green.parse().map_err(|_| bad())?,
+            });
+        }
+        Ok(SignalPlan { phases })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        SignalPlan::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// Backtracking k-colouring of the conflict graph
+fn color(
+    matrix: &ConflictMatrix,
+    movements: &[Movement],
+    colors: &mut [usize],
+    next: usize,
+    k: usize,
+) -> bool {
+    if next == movements.len() {
+        return true;
+    }
+    let m = movements[next];
+    for c in 0..k {
+        let clash = movements[..next]
+            .iter()
+            .any(|other| colors[other.index()] == c && matrix.conflicts(m, *other));
+        if !clash {
+            colors[m.index()] = c;
+            if color(matrix, movements, colors, next + 1, k) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Steps through a fixed-time plan one frame at a time
+#[derive(Clone)]
+pub struct FixedTimeController {
+    plan: SignalPlan,
+    phase: usize,
+    elapsed: u32,
+}
+
+impl FixedTimeController {
+    pub fn new(plan: SignalPlan) -> Self {
+        FixedTimeController {
+            plan,
+            phase: 0,
+            elapsed: 0,
+        }
+    }
+
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    // Frames left before the current phase ends
+    pub fn remaining(&self) -> u32 {
+        self.plan.phases[self.phase].green + 1 - self.elapsed
+    }
+}
+
+impl SignalController for FixedTimeController {
+    // Fixed time ignores detectors
+    fn tick(&mut self, _calls: &[bool; MOVEMENT_COUNT]) {
+        self.elapsed += 1;
+        if self.elapsed > self.plan.phases[self.phase].green {
+            self.phase = (self.phase + 1) % self.plan.phases.len();
+            self.elapsed = 0;
+        }
+    }
+
+    fn is_green(&self, movement: Movement) -> bool {
+        self.plan.phases[self.phase].movements.contains(&movement)
+    }
+
+    // [phase, elapsed]
+    fn state(&self) -> Vec<u32> {
+        vec![self.phase as u32, self.elapsed]
+    }
+
+    fn restore(&mut self, state: &[u32]) -> Result<(), String> {
+        match state {
+            // A phase ends once `elapsed` passes its green, so no more than
+            // that can have elapsed
+            [phase, elapsed]
+                if (*phase as usize) < self.plan.phases.len()
+                    && *elapsed <= self.plan.phases[*phase as usize].green =>
+            {
+                self.phase = *phase as usize;
+                self.elapsed = *elapsed;
+                Ok(())
+            }
+            _ => Err("signal state doesn't match this plan".to_string()),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn SignalController> {
+        Box::new(self.clone())
+    }
+
+    // Red heads count down to the next phase serving their movement
+    fn indication(&self, movement: Movement) -> Indication {
+        let phases = &self.plan.phases;
+        if phases[self.phase].movements.contains(&movement) {
+            return Indication {
+                state: LightState::Green,
+                remaining: Some(self.remaining()),
+            };
+        }
+        let mut wait = self.remaining();
+        for k in 1..phases.len() {
+            let phase = &phases[(self.phase + k) % phases.len()];
+            if phase.movements.contains(&movement) {
+                return Indication {
+                    state: LightState::Red,
+                    remaining: Some(wait),
+                };
+            }
+            wait += phase.green + 1;
+        }
+        Indication {
+            state: LightState::Red,
+            remaining: None,
+        }
+    }
+
+    fn status(&self) -> Vec<PhaseStatus> {
+        vec![PhaseStatus {
+            name: format!("Phase {}", self.phase + 1),
+            interval: "green",
+            remaining: Some(self.remaining()),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    fn simulation() -> Simulation {
+        let plan = SignalPlan::round_robin(300);
+        let mut sim = Simulation::with_seed(Box::new(FixedTimeController::new(plan)), 7);
+        sim.set_demand([600.0; 4]);
+        sim
+    }
+
+    #[test]
+    fn snapshot_round_trips_the_plan_position() {
+        let mut sim = simulation();
+        for _ in 0..500 {
+            sim.step();
+        }
+        let text = sim.snapshot();
+        let mut restored = simulation();
+        restored.restore(&text).unwrap();
+        assert_eq!(restored.snapshot(), text);
+        assert_eq!(restored.signals().state(), [1, 199]);
+    }
+
+    #[test]
+    fn snapshot_past_the_end_of_a_phase_is_refused() {
+        let mut sim = simulation();
+        for _ in 0..500 {
+            sim.step();
+        }
+        let text = sim
+            .snapshot()
+            .replace("[signals]\n1 199\n", "[signals]\n1 301\n");
+        let mut restored = simulation();
+        assert!(restored.restore(&text).is_err());
+        assert_eq!(restored.frame(), 1);
+
+        let mut controller = FixedTimeController::new(SignalPlan::round_robin(300));
+        assert!(controller.restore(&[1, 300]).is_ok());
+        assert!(controller.restore(&[1, 301]).is_err());
+    }
+}
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sdl2::rect::Rect;
+
+use crate::conflict::{self, MOVEMENT_COUNT, Movement};
+use crate::export::{direction_name, light_state_name, turn_name};
+use crate::intersection;
+use crate::signal::{self, SignalController};
+use crate::snapshot::{self, Fields, HEADER, SNAPSHOT_VERSION};
+use crate::stats::APPROACHES;
+use crate::stats::Statistics;
+use crate::traffic_light::{Indication, LightState};
+use crate::vehicle::{self, Direction, Hold, Vehicle};
+use crate::watchdog::{Watchdog, WatchdogConfig, WatchdogEvent};
+
+pub const SAFE_DISTANCE: i32 = 50; // Minimum safe distance between vehicles
+pub const FRAMES_PER_SECOND: u32 = 60; // Simulated time per frame is 1/60 s
+
+// Everything that moves: vehicles, the signal controller and spawn cooldowns.
+// The SDL loop feeds it input and draws it; `step` advances it one frame.
+// All randomness comes from one seeded RNG, so the same seed and the same
+// inputs on the same frames always give the same run.
+#[derive(Clone)]
+pub struct Simulation {
+    pub vehicles: Vec<Vehicle>,
+    signals: Box<dyn SignalController>,
+    // States forced by hand, in place of the controller's, per movement
+    overrides: [Option<LightState>; MOVEMENT_COUNT],
+    // Random arrivals per hour at each approach, in `APPROACHES` order
+    demand: [f64; 4],
+    // Gap kept to the vehicle ahead, and at the spawn points
+    safe_distance: i32,
+    // Track the last spawn time for each direction to enforce safe distance
+    last_spawn: HashMap<Direction, i32>,
+    frame: i32,
+    next_id: u32,
+    seed: u64,
+    rng: ChaCha8Rng,
+    pub stats: Statistics,
+    pub watchdog: Watchdog,
+    // Watchdog reports not yet collected by the caller
+    pub watchdog_events: Vec<WatchdogEvent>,
+}
+
+// The simulation at one frame, kept for rewinding. The append-only logs in
+// `stats` are left out and only their lengths kept, so a checkpoint costs the
+// same however long the run has been going.
+#[derive(Clone)]
+pub struct Checkpoint {
+    sim: Simulation,
+    logs: (usize, usize),
+}
+
+impl Checkpoint {
+    // The state at this checkpoint, without the statistics logs
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+}
+
+impl Simulation {
+    // A run with a fresh random seed
+    pub fn new(signals: Box<dyn SignalController>) -> Self {
+        Simulation::with_seed(signals, rand::random())
+    }
+
+    pub fn with_seed(signals: Box<dyn SignalController>, seed: u64) -> Self {
+        let mut last_spawn = HashMap::new();
+        last_spawn.insert(Direction::North, 0);
+        last_spawn.insert(Direction::South, 0);
+        last_spawn.insert(Direction::East, 0);
+        last_spawn.insert(Direction::West, 0);
+        Simulation {
+            vehicles: Vec::new(),
+            signals,
+            overrides: [None; MOVEMENT_COUNT],
+            demand: [0.0; 4],
+            safe_distance: SAFE_DISTANCE,
+            last_spawn,
+            frame: 1,
+            next_id: 1,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            stats: Statistics::default(),
+            watchdog: Watchdog::new(WatchdogConfig::default()),
+            watchdog_events: Vec::new(),
+        }
+    }
+
+    pub fn frame(&self) -> i32 {
+        self.frame
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn signals(&self) -> &dyn SignalController {
+        self.signals.as_ref()
+    }
+
+    // What a movement's signal shows: its override if one is set, otherwise
+    // the controller's indication
+    pub fn indication(&self, movement: Movement) -> Indication {
+        match self.overrides[movement.index()] {
+            Some(state) => Indication {
+                state,
+                remaining: None,
+            },
+            None => self.signals.indication(movement),
+        }
+    }
+
+    fn is_green(&self, movement: Movement) -> bool {
+        match self.overrides[movement.index()] {
+            Some(state) => state == LightState::Green,
+            None => self.signals.is_green(movement),
+        }
+    }
+
+    // Force a movement's signal to `state`, or hand it back to the controller
+    // with None. The controller keeps running underneath.
+    pub fn set_override(&mut self, movement: Movement, state: Option<LightState>) {
+        self.overrides[movement.index()] = state;
+    }
+
+    pub fn overridden(&self, movement: Movement) -> bool {
+        self.overrides[movement.index()].is_some()
+    }
+
+    // Vehicles per hour arriving by themselves at each approach, in
+    // `APPROACHES` order. Arrivals are drawn each frame from the run's RNG;
+    // one that finds its entry too crowded is dropped. Like the signal
+    // timings this is setup, not state, so snapshots don't carry it.
+    pub fn set_demand(&mut self, per_hour: [f64; 4]) {
+        self.demand = per_hour;
+    }
+
+    pub fn demand(&self) -> [f64; 4] {
+        self.demand
+    }
+
+    // Gap in pixels vehicles keep to the one ahead, `SAFE_DISTANCE` unless
+    // set otherwise. Setup like the demand, not saved in snapshots.
+    pub fn set_safe_distance(&mut self, pixels: i32) {
+        self.safe_distance = pixels;
+    }
+
+    pub fn safe_distance(&self) -> i32 {
+        self.safe_distance
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            sim: Simulation {
+                vehicles: self.vehicles.clone(),
+                signals: self.signals.clone(),
+                overrides: self.overrides,
+                demand: self.demand,
+                safe_distance: self.safe_distance,
+                last_spawn: self.last_spawn.clone(),
+                frame: self.frame,
+                next_id: self.next_id,
+                seed: self.seed,
+                rng: self.rng.clone(),
+                stats: self.stats.without_logs(),
+                watchdog: self.watchdog.clone(),
+                watchdog_events: Vec::new(),
+            },
+            logs: self.stats.log_lengths(),
+        }
+    }
+
+    // Go back to a checkpoint taken earlier in this same run, keeping the
+    // part of the statistics logs written before it
+    pub fn rewind(&mut self, checkpoint: &Checkpoint) {
+        let mut records = std::mem::take(&mut self.stats.records);
+        let mut signal_log = std::mem::take(&mut self.stats.signal_log);
+        records.truncate(checkpoint.logs.0);
+        signal_log.truncate(checkpoint.logs.1);
+        *self = checkpoint.sim.clone();
+        self.stats.records = records;
+        self.stats.signal_log = signal_log;
+    }
+
+    // The whole state as text; see `snapshot` for the layout
+    pub fn snapshot(&self) -> String {
+        let mut out = format!("{} {}\n[simulation]\n", HEADER, SNAPSHOT_VERSION);
+        out.push_str(&format!("frame {}\n", self.frame));
+        out.push_str(&format!("next_id {}\n", self.next_id));
+        out.push_str(&format!("rng {} {}\n", self.seed, self.rng.get_word_pos()));
+        for d in APPROACHES {
+            out.push_str(&format!(
+                "last_spawn {} {}\n",
+                direction_name(d),
+                self.last_spawn[&d]
+            ));
+        }
+        for m in Movement::all() {
+            if let Some(state) = self.overrides[m.index()] {
+                out.push_str(&format!(
+                    "override {} {} {}\n",
+                    direction_name(m.approach),
+                    turn_name(m.turn),
+                    light_state_name(state)
+                ));
+            }
+        }
+        out.push_str("[signals]\n");
+        let state: Vec<String> = self.signals.state().iter().map(u32::to_string).collect();
+        out.push_str(&format!("{}\n", state.join(" ")));
+        out.push_str("[vehicles]\n");
+        for v in &self.vehicles {
+            out.push_str(&format!("{}\n", v.snapshot_line()));
+        }
+        out.push_str("[stats]\n");
+        for line in self.stats.snapshot_lines() {
+            out.push_str(&format!("{}\n", line));
+        }
+        out.push_str("[watchdog]\n");
+        for line in self.watchdog.snapshot_lines() {
+            out.push_str(&format!("{}\n", line));
+        }
+        out
+    }
+
+    // Replace the state with a snapshot taken by `snapshot`. Nothing changes
+    // if the text can't be read or doesn't fit this simulation's controller.
+    pub fn restore(&mut self, text: &str) -> Result<(), String> {
+        let (version, sections) = snapshot::sections(text)?;
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, lines)| lines.as_slice())
+                .ok_or(format!("missing [{}] section", name))
+        };
+
+        let (mut frame, mut next_id, mut rng) = (None, None, None);
+        let mut last_spawn = self.last_spawn.clone();
+        let mut overrides = [None; MOVEMENT_COUNT];
+        for line in section("simulation")? {
+            let mut f = Fields::new(line);
+            match f.word()? {
+                "frame" => frame = Some(f.value()?),
+                "next_id" => next_id = Some(f.value()?),
+                "rng" => rng = Some((f.value::<u64>()?, f.value::<u128>()?)),
+                "last_spawn" => {
+                    let direction = f.direction()?;
+                    last_spawn.insert(direction, f.value()?);
+                }
+                "override" if version < 4 => {
+                    return Err(format!("override in a version {} snapshot", version));
+                }
+                "override" => {
+                    let movement = Movement {
+                        approach: f.direction()?,
+                        turn: f.turn()?,
+                    };
+                    overrides[movement.index()] = Some(f.light_state()?);
+                }
+                other => return Err(format!("unknown simulation item '{}'", other)),
+            }
+            f.end()?;
+        }
+        let frame = frame.ok_or("missing frame")?;
+        let next_id = next_id.ok_or("missing next_id")?;
+        let (seed, word_pos) = rng.ok_or("missing rng")?;
+
+        let signal_state = section("signals")?
+            .iter()
+            .map(|line| Fields::new(line).rest::<u32>())
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        let vehicles = section("vehicles")?
+            .iter()
+            .map(|line| Vehicle::from_snapshot_line(line))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut stats = Statistics::default();
+        stats.restore(section("stats")?)?;
+        let mut watchdog = Watchdog::new(self.watchdog.config.clone());
+        watchdog.restore(section("watchdog")?)?;
+        self.signals.restore(&signal_state)?;
+
+        self.vehicles = vehicles;
+        self.last_spawn = last_spawn;
+        self.overrides = overrides;
+        self.frame = frame;
+        self.next_id = next_id;
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.rng.set_word_pos(word_pos);
+        self.stats = stats;
+        self.watchdog = watchdog;
+        self.watchdog_events.clear();
+        Ok(())
+    }
+
+    // Spawn a vehicle heading `direction` if it's safe (not too close to
+    // existing ones). Returns whether a vehicle was added.
+    pub fn try_spawn(&mut self, direction: Direction) -> bool {
+        if !is_safe_to_spawn(
+            &self.vehicles,
+            direction,
+            &self.last_spawn,
+            self.frame,
+            self.safe_distance,
+        ) {
+            return false;
+        }
+        let vehicle = Vehicle::new(self.next_id, direction, &mut self.rng);
+        self.next_id += 1;
+        self.stats.on_spawn(&vehicle, self.frame);
+        self.vehicles.push(vehicle);
+        self.last_spawn.insert(direction, self.frame);
+        true
+    }
+
+    // Spawn heading a direction drawn from the simulation's RNG
+    pub fn try_spawn_random(&mut self) -> bool {
+        let direction = match self.rng.random_range(0..4) {
+            0 => Direction::North,
+            1 => Direction::South,
+            2 => Direction::East,
+            3 => Direction::West,
+            _ => unreachable!(),
+        };
+        self.try_spawn(direction)
+    }
+
+    pub fn step(&mut self) {
+        // The RNG is only drawn for approaches with demand, so runs without
+        // any are the same as before it existed
+        let per_frame = 3600.0 * FRAMES_PER_SECOND as f64;
+        for (approach, rate) in APPROACHES.into_iter().zip(self.demand) {
+            if rate > 0.0 && self.rng.random::<f64>() < rate / per_frame {
+                self.try_spawn(approach);
+            }
+        }
+
+        self.signals.tick(&signal::detector_calls(&self.vehicles));
+        let mut states = [LightState::Red; MOVEMENT_COUNT];
+        for m in Movement::all() {
+            states[m.index()] = self.indication(m).state;
+        }
+        self.stats.on_signals(states, self.frame);
+
+        // Compute tentative positions (with traffic light checks)
+        let tentatives: Vec<Vehicle> = self
+            .vehicles
+            .iter()
+            .map(|v| {
+                let mut tentative_v = v.clone();
+                let light_state = if self.is_green(tentative_v.movement()) {
+                    LightState::Green
+                } else {
+                    LightState::Red
+                };
+
+                // Only move if not stopped by light or vehicle ahead
+                tentative_v.update(light_state);
+                tentative_v
+            })
+            .collect();
+
+        // Check safe distances between vehicles in the same direction
+        let mut safe_to_move = vec![true; tentatives.len()];
+        let mut blocked_by: Vec<Option<usize>> = vec![None; tentatives.len()];
+        let mut holds: Vec<Option<Hold>> = vec![None; tentatives.len()];
+        for i in 0..tentatives.len() {
+            let current = &tentatives[i];
+            let dir = current.direction();
+
+            // Find closest vehicle ahead in the same direction
+            let closest_ahead = tentatives
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.direction() == dir)
+                .filter_map(|(j, other)| {
+                    let distance = match dir {
+                        Direction::North => {
+                            current.rect.y() - (other.rect.y() + other.rect.height() as i32)
+                        }
+                        Direction::South => {
+                            other.rect.y() - (current.rect.y() + current.rect.height() as i32)
+                        }
+                        Direction::East => {
+                            other.rect.x() - (current.rect.x() + current.rect.width() as i32)
+                        }
+                        Direction::West => {
+                            current.rect.x() - (other.rect.x() + other.rect.width() as i32)
+                        }
+                    };
+                    if distance >= 0 {
+                        Some((distance, j))
+                    } else {
+                        None
+                    }
+                })
+                .min();
+
+            // Block movement if vehicle is too close
+            if let Some((distance, j)) = closest_ahead
+                && distance < self.safe_distance
+            {
+                safe_to_move[i] = false;
+                blocked_by[i] = Some(j);
+                holds[i] = Some(Hold::Following);
+            }
+        }
+
+        // Don't block the box: hold at the line while the exit is backed up
+        for i in 0..tentatives.len() {
+            let entering = tentatives[i].in_intersection && !self.vehicles[i].in_intersection;
+            let blocker = if entering {
+                conflict::exit_blocker(&tentatives[i], &self.vehicles)
+            } else {
+                None
+            };
+            if let Some(j) = blocker {
+                safe_to_move[i] = false;
+                blocked_by[i] = Some(j);
+                holds[i] = Some(Hold::Exit);
+                if !self.vehicles[i].held_for_exit {
+                    self.stats.box_entries_held += 1;
+                }
+            }
+            self.vehicles[i].held_for_exit = blocker.is_some();
+        }
+
+        // Right-of-way inside the box and final overlap check
+        let moves = conflict::resolve(&self.vehicles, &tentatives, safe_to_move, &mut blocked_by);
+
+        // Update original vehicles only if safe
+        let ids: Vec<u32> = self.vehicles.iter().map(|v| v.id()).collect();
+        for (i, vehicle) in self.vehicles.iter_mut().enumerate() {
+            let moved = moves[i] && tentatives[i].rect() != vehicle.rect();
+            if moves[i] {
+                *vehicle = tentatives[i].clone();
+                vehicle.held_for_exit = false;
+            }
+            if moved {
+                vehicle.stalled = 0;
+            } else {
+                vehicle.stalled += 1;
+            }
+            vehicle.blocked_by = if moves[i] {
+                None
+            } else {
+                blocked_by[i].map(|j| ids[j])
+            };
+            // Anything `resolve` stopped was held for a conflict; a vehicle
+            // free to move that stayed put was held by its light
+            vehicle.held = match (moved, moves[i]) {
+                (true, _) => None,
+                (false, true) => Some(Hold::Light),
+                (false, false) => holds[i].or(Some(Hold::Conflict)),
+            };
+        }
+
+        // A vehicle that has just stopped inside the box with its exit backed
+        // up is blocking it; one yielding or following inside it isn't
+        for v in &self.vehicles {
+            if v.stalled == 1
+                && conflict::in_box(v.rect())
+                && conflict::exit_blocker(v, &self.vehicles).is_some()
+            {
+                self.stats.box_blocked += 1;
+            }
+        }
+
+        // Look for stuck vehicles and circular waits
+        for event in self.watchdog.check(&self.vehicles, self.frame) {
+            match &event {
+                WatchdogEvent::Stuck { .. } => self.stats.stuck_vehicles += 1,
+                WatchdogEvent::Deadlock { .. } => self.stats.deadlocks += 1,
+                WatchdogEvent::Removed { id, .. } => {
+                    if let Some(v) = self.vehicles.iter().find(|v| v.id() == *id) {
+                        self.stats.on_removed(v);
+                    }
+                    self.vehicles.retain(|v| v.id() != *id);
+                }
+            }
+            self.watchdog_events.push(event);
+        }
+
+        self.stats.on_step(&self.vehicles);
+
+        // Remove vehicles that have driven off the world
+        let (frame, stats) = (self.frame, &mut self.stats);
+        let world = intersection::world_bounds();
+        self.vehicles.retain(|v| {
+            let keep = on_road(v, world);
+            if !keep {
+                stats.on_exit(v, frame);
+            }
+            keep
+        });
+
+        self.frame += 1;
+    }
+}
+
+// A length of simulated time in frames, None if it's too long to count
+pub fn frames(seconds: u32) -> Option<i32> {
+    seconds
+        .checked_mul(FRAMES_PER_SECOND)
+        .and_then(|f| i32::try_from(f).ok())
+}
+
+// Frames a lone vehicle takes from spawn until it leaves the world
+pub fn free_flow_frames(movement: Movement) -> u32 {
+    let mut vehicle = Vehicle::with_turn(0, movement.approach, movement.turn);
+    let world = intersection::world_bounds();
+    let mut frames = 0;
+    while on_road(&vehicle, world) {
+        vehicle.update(LightState::Green);
+        frames += 1;
+    }
+    frames
+}
+
+fn is_safe_to_spawn(
+    vehicles: &[Vehicle],
+    direction: Direction,
+    last_spawn: &HashMap<Direction, i32>,
+    current_frame: i32,
+    safe_distance: i32,
+) -> bool {
+    // Enforce minimum time between spawns in the same direction
+    if let Some(last_frame) = last_spawn.get(&direction)
+        && current_frame - last_frame < safe_distance / 2
+    {
+        return false;
+    }
+
+    // Calculate spawn point based on direction
+    let spawn = vehicle::spawn_rect(direction);
+    let (spawn_coord, is_vertical) = match direction {
+        Direction::North | Direction::South => (spawn.y(), true), // Bottom or top edge
+        Direction::East | Direction::West => (spawn.x(), false),  // Left or right edge
+    };
+
+    // Check distance from existing vehicles in the same direction
+    for vehicle in vehicles.iter().filter(|v| v.direction() == direction) {
+        let vehicle_pos = if is_vertical {
+            vehicle.rect.y() + vehicle.rect.height() as i32
+        } else {
+            vehicle.rect.x() + vehicle.rect.width() as i32
+        };
+
+        let distance = (vehicle_pos - spawn_coord).abs();
+        if distance < safe_distance {
+            return false; // Existing vehicle too close to spawn point
+        }
+    }
+
+    // A queue backed up to the edge may reach into the spawn point itself
+    !vehicles.iter().any(|v| v.rect().has_intersection(spawn))
+}
+
+// How far past the edge of the world a vehicle drives before it's removed,
+// so it has fully left the roads
+const EXIT_MARGIN: i32 = 50;
+
+// Whether a vehicle is still on the roads of a world with these bounds
+fn on_road(vehicle: &Vehicle, world: Rect) -> bool {
+    let rect = vehicle.rect();
+    match vehicle.direction() {
+        Direction::North => rect.y() > world.y() - EXIT_MARGIN,
+        Direction::South => rect.y() < world.bottom() + EXIT_MARGIN,
+        Direction::East => rect.x() < world.right() + EXIT_MARGIN,
+        Direction::West => rect.x() > world.x() - EXIT_MARGIN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::{FixedTimeController, SignalPlan};
+    use crate::vehicle::Turn;
+
+    #[test]
+    fn yielding_in_the_box_is_not_blocking_it() {
+        let plan = SignalPlan::round_robin(600);
+        let mut sim = Simulation::with_seed(Box::new(FixedTimeController::new(plan)), 1);
+        // Northbound and eastbound straight, each about to reach the other's lane
+        for (id, direction, rect) in [
+            (1, Direction::North, Rect::new(415, 437, 20, 40)),
+            (2, Direction::East, Rect::new(374, 415, 40, 20)),
+        ] {
+            let mut v = Vehicle::with_turn(id, direction, Turn::Straight);
+            v.rect = rect;
+            v.in_intersection = true;
+            v.has_turned = true;
+            sim.vehicles.push(v);
+        }
+        sim.step();
+        assert!(sim.vehicles.iter().any(|v| v.held == Some(Hold::Conflict)));
+        assert_eq!(sim.stats.box_blocked, 0);
+    }
+
+    #[test]
+    fn overrides_need_a_version_4_snapshot() {
+        let plan = SignalPlan::round_robin(600);
+        let new = || Simulation::with_seed(Box::new(FixedTimeController::new(plan.clone())), 1);
+        let mut sim = new();
+        let left = Movement {
+            approach: Direction::North,
+            turn: Turn::Left,
+        };
+        sim.set_override(left, Some(LightState::Green));
+        sim.step();
+        let text = sim.snapshot();
+        assert!(text.starts_with("road-intersection-snapshot 4\n"));
+        let mut restored = new();
+        restored.restore(&text).unwrap();
+        assert!(restored.overridden(left));
+
+        let old = text.replace(
+            "road-intersection-snapshot 4",
+            "road-intersection-snapshot 3",
+        );
+        assert!(new().restore(&old).is_err());
+        let old = old.replace("override north left green\n", "");
+        let mut restored = new();
+        restored.restore(&old).unwrap();
+        assert!(!restored.overridden(left));
+        assert_eq!(restored.frame(), sim.frame());
+
+        let older = old.replace(
+            "road-intersection-snapshot 3",
+            "road-intersection-snapshot 2",
+        );
+        assert!(new().restore(&older).is_err());
+    }
+}
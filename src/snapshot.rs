@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::{FromStr, SplitWhitespace};
+
+use crate::export::{direction_from_name, light_state_from_name, turn_from_name};
+use crate::simulation::Simulation;
+use crate::traffic_light::LightState;
+use crate::vehicle::{Direction, Turn};
+
+// Bumped whenever a section or line changes layout or meaning
+pub const SNAPSHOT_VERSION: u32 = 4;
+// Oldest version still read: version 3 is version 4 without `override` lines
+pub const OLDEST_SNAPSHOT_VERSION: u32 = 3;
+
+pub const HEADER: &str = "road-intersection-snapshot";
+
+// A snapshot is plain text: a header line, then sections that each start with
+// `[name]` and hold one item per line as whitespace-separated words. Every
+// part of the simulation writes and reads its own section:
+//
+//   road-intersection-snapshot 4
+//   [simulation]   frame, next id, seed + RNG position, spawn cooldowns,
+//                  signal overrides
+//   [signals]      controller intervals and timers
+//   [vehicles]     one line per vehicle with every field
+//   [stats]        counters, records and the signal log
+//   [watchdog]     what has already been reported
+pub fn save(sim: &Simulation, path: &Path) -> io::Result<()> {
+    fs::write(path, sim.snapshot())
+}
+
+// Restore `sim` from a file. The controller must be built the same way as
+// the one that was saved; only its state is in the snapshot.
+pub fn load(sim: &mut Simulation, path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    sim.restore(&text)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// A section's name and its lines
+pub type Section<'a> = (&'a str, Vec<&'a str>);
+
+// The snapshot's version and each section, in file order
+pub fn sections(text: &str) -> Result<(u32, Vec<Section<'_>>), String> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    let version = lines
+        .next()
+        .and_then(|l| l.strip_prefix(HEADER))
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|v| (OLDEST_SNAPSHOT_VERSION..=SNAPSHOT_VERSION).contains(v))
+        .ok_or(format!(
+            "not a version {} to {} snapshot",
+            OLDEST_SNAPSHOT_VERSION, SNAPSHOT_VERSION
+        ))?;
+    let mut sections: Vec<Section> = Vec::new();
+    for line in lines {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((name, Vec::new()));
+        } else if let Some((_, body)) = sections.last_mut() {
+            body.push(line);
+        } else {
+            return Err(format!("'{}' outside any section", line));
+        }
+    }
+    Ok((version, sections))
+}
+
+// The words of one snapshot line, read front to back
+pub struct Fields<'a> {
+    line: &'a str,
+    words: SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Fields {
+            line,
+            words: line.split_whitespace(),
+        }
+    }
+
+    pub fn word(&mut self) -> Result<&'a str, String> {
+        self.words
+            .next()
+            .ok_or_else(|| format!("'{}' is too short", self.line))
+    }
+
+    pub fn value<T: FromStr>(&mut self) -> Result<T, String> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| format!("can't read '{}' in '{}'", word, self.line))
+    }
+
+    // `-` stands for None
+    pub fn optional<T: FromStr>(&mut self) -> Result<Option<T>, String> {
+        if self.words.clone().next() == Some("-") {
+            self.words.next();
+            return Ok(None);
+        }
+        self.value().map(Some)
+    }
+
+    pub fn direction(&mut self) -> Result<Direction, String> {
+        let word = self.word()?;
+        direction_from_name(word)
+            .ok_or_else(|| format!("no direction '{}' in '{}'", word, self.line))
+    }
+
+    pub fn turn(&mut self) -> Result<Turn, String> {
+        let word = self.word()?;
+        turn_from_name(word).ok_or_else(|| format!("no turn '{}' in '{}'", word, self.line))
+    }
+
+    pub fn light_state(&mut self) -> Result<LightState, String> {
+        let word = self.word()?;
+        light_state_from_name(word)
+            .ok_or_else(|| format!("no light state '{}' in '{}'", word, self.line))
+    }
+
+    // Everything left on the line
+    pub fn rest<T: FromStr>(&mut self) -> Result<Vec<T>, String> {
+        let mut out = Vec::new();
+        while self.words.clone().next().is_some() {
+            out.push(self.value()?);
+        }
+        Ok(out)
+    }
+
+    // Fail if anything is left over
+    pub fn end(mut self) -> Result<(), String> {
+        match self.words.next() {
+            None => Ok(()),
+            Some(_) => Err(format!("'{}' is too long", self.line)),
+        }
+    }
+}
+
+// `-` for None, the value otherwise
+pub fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
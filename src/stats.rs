@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::conflict::{MOVEMENT_COUNT, Movement};
+use crate::export::{direction_name, light_state_name, turn_name};
+use crate::simulation::{self, FRAMES_PER_SECOND};
+use crate::snapshot::{self, Fields};
+use crate::traffic_light::LightState;
+use crate::vehicle::{Direction, Vehicle};
+
+pub const APPROACHES: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+// One vehicle's trip, from spawn until it leaves the screen (all in frames)
+#[derive(Clone, Debug)]
+pub struct VehicleRecord {
+    pub id: u32,
+    pub movement: Movement,
+    pub spawn_frame: i32,
+    pub exit_frame: Option<i32>, // None while on the road or if removed
+    pub stopped_frames: u32,
+    pub delay_frames: u32, // travel time beyond free flow
+    pub removed: bool,     // taken off the road by the watchdog
+}
+
+// A movement's signal changing what it shows. Vehicles only go on green.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignalChange {
+    pub frame: i32,
+    pub movement: Movement,
+    pub state: LightState,
+}
+
+// Aggregates for one approach or movement. Delays are in seconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlowSummary {
+    pub throughput: u32,
+    pub mean_delay: f64,
+    pub p95_delay: f64,
+    pub max_queue: u32,
+}
+
+// Counters and per-vehicle records collected while the simulation runs
+#[derive(Clone, Debug)]
+pub struct Statistics {
+    // Times a vehicle was held at the line because its exit was backed up
+    pub box_entries_held: u32,
+    // Times a vehicle came to a stop inside the box with its exit backed up
+    pub box_blocked: u32,
+    // Vehicles the watchdog found stuck, and circular waits among them
+    pub stuck_vehicles: u32,
+    pub deadlocks: u32,
+    pub spawned: u32,
+    // Finished trips in the order vehicles left
+    pub records: Vec<VehicleRecord>,
+    // Every change of a movement's signal in order, starting with what each
+    // showed on the first frame
+    pub signal_log: Vec<SignalChange>,
+    signals: [Option<LightState>; MOVEMENT_COUNT],
+    active: HashMap<u32, VehicleRecord>,
+    free_flow: [u32; MOVEMENT_COUNT],
+    // Longest stopped queue seen per movement and per approach
+    max_queue: [u32; MOVEMENT_COUNT],
+    max_approach_queue: [u32; 4],
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        let mut free_flow = [0; MOVEMENT_COUNT];
+        for m in Movement::all() {
+            free_flow[m.index()] = simulation::free_flow_frames(m);
+        }
+        Statistics {
+            box_entries_held: 0,
+            box_blocked: 0,
+            stuck_vehicles: 0,
+            deadlocks: 0,
+            spawned: 0,
+            records: Vec::new(),
+            signal_log: Vec::new(),
+            signals: [None; MOVEMENT_COUNT],
+            active: HashMap::new(),
+            free_flow,
+            max_queue: [0; MOVEMENT_COUNT],
+            max_approach_queue: [0; 4],
+        }
+    }
+}
+
+impl Statistics {
+    pub fn on_spawn(&mut self, vehicle: &Vehicle, frame: i32) {
+        self.spawned += 1;
+        self.active.insert(
+            vehicle.id(),
+            VehicleRecord {
+                id: vehicle.id(),
+                movement: vehicle.movement(),
+                spawn_frame: frame,
+                exit_frame: None,
+                stopped_frames: 0,
+                delay_frames: 0,
+                removed: false,
+            },
+        );
+    }
+
+    // Called once per frame with what each movement's signal shows
+    pub fn on_signals(&mut self, states: [LightState; MOVEMENT_COUNT], frame: i32) {
+        for m in Movement::all() {
+            let state = states[m.index()];
+            if self.signals[m.index()] != Some(state) {
+                self.signal_log.push(SignalChange {
+                    frame,
+                    movement: m,
+                    state,
+                });
+                self.signals[m.index()] = Some(state);
+            }
+        }
+    }
+
+    // Called once per frame after vehicles have moved
+    pub fn on_step(&mut self, vehicles: &[Vehicle]) {
+        for v in vehicles.iter().filter(|v| v.stalled > 0) {
+            if let Some(record) = self.active.get_mut(&v.id()) {
+                record.stopped_frames += 1;
+            }
+        }
+        let queue = queues(vehicles);
+        for m in Movement::all() {
+            let i = m.index();
+            self.max_queue[i] = self.max_queue[i].max(queue[i]);
+        }
+        for (a, approach) in APPROACHES.iter().enumerate() {
+            let total = Movement::all()
+                .iter()
+                .filter(|m| m.approach == *approach)
+                .map(|m| queue[m.index()])
+                .sum::<u32>();
+            self.max_approach_queue[a] = self.max_approach_queue[a].max(total);
+        }
+    }
+
+    pub fn on_exit(&mut self, vehicle: &Vehicle, frame: i32) {
+        if let Some(mut record) = self.active.remove(&vehicle.id()) {
+            let travel = (frame - record.spawn_frame).max(0) as u32;
+            record.exit_frame = Some(frame);
+            record.delay_frames = travel.saturating_sub(self.free_flow[record.movement.index()]);
+            self.records.push(record);
+        }
+    }
+
+    pub fn on_removed(&mut self, vehicle: &Vehicle) {
+        if let Some(mut record) = self.active.remove(&vehicle.id()) {
+            record.removed = true;
+            self.records.push(record);
+        }
+    }
+
+    // Vehicles currently on the road, by id
+    pub fn active(&self) -> impl Iterator<Item = &VehicleRecord> {
+        self.active.values()
+    }
+
+    pub fn by_movement(&self) -> Vec<(Movement, FlowSummary)> {
+        Movement::all()
+            .into_iter()
+            .map(|m| {
+                let summary = self.summarise(|r| r.movement == m, self.max_queue[m.index()]);
+                (m, summary)
+            })
+            .collect()
+    }
+
+    pub fn by_approach(&self) -> Vec<(Direction, FlowSummary)> {
+        APPROACHES
+            .iter()
+            .enumerate()
+            .map(|(a, approach)| {
+                let summary = self.summarise(
+                    |r| r.movement.approach == *approach,
+                    self.max_approach_queue[a],
+                );
+                (*approach, summary)
+            })
+            .collect()
+    }
+
+    pub fn overall(&self) -> FlowSummary {
+        let max_queue = self.max_approach_queue.iter().copied().max().unwrap_or(0);
+        self.summarise(|_| true, max_queue)
+    }
+
+    fn summarise(&self, filter: impl Fn(&VehicleRecord) -> bool, max_queue: u32) -> FlowSummary {
+        let mut delays: Vec<u32> = self
+            .records
+            .iter()
+            .filter(|r| r.exit_frame.is_some() && filter(r))
+            .map(|r| r.delay_frames)
+            .collect();
+        delays.sort_unstable();
+        let seconds = |frames: u32| frames as f64 / FRAMES_PER_SECOND as f64;
+        let mean = if delays.is_empty() {
+            0.0
+        } else {
+            delays.iter().map(|d| seconds(*d)).sum::<f64>() / delays.len() as f64
+        };
+        FlowSummary {
+            throughput: delays.len() as u32,
+            mean_delay: mean,
+            p95_delay: percentile(&delays, 95).map_or(0.0, seconds),
+            max_queue,
+        }
+    }
+
+    // Lengths of the append-only `records` and `signal_log`
+    pub fn log_lengths(&self) -> (usize, usize) {
+        (self.records.len(), self.signal_log.len())
+    }
+
+    // A copy with empty `records` and `signal_log`, which is cheap however
+    // long the run has been going
+    pub fn without_logs(&self) -> Statistics {
+        Statistics {
+            records: Vec::new(),
+            signal_log: Vec::new(),
+            signals: self.signals,
+            active: self.active.clone(),
+            free_flow: self.free_flow,
+            max_queue: self.max_queue,
+            max_approach_queue: self.max_approach_queue,
+            ..*self
+        }
+    }
+
+    // Everything collected so far, for simulation snapshots. Records are
+    // `id approach turn spawn_frame exit_frame stopped delay removed`.
+    pub fn snapshot_lines(&self) -> Vec<String> {
+        let numbers = |values: &[u32]| values.iter().map(|v| format!(" {}", v)).collect::<String>();
+        let record = |r: &VehicleRecord| {
+            format!(
+                "{} {} {} {} {} {} {} {}",
+                r.id,
+                direction_name(r.movement.approach),
+                turn_name(r.movement.turn),
+                r.spawn_frame,
+                snapshot::optional(r.exit_frame),
+                r.stopped_frames,
+                r.delay_frames,
+                r.removed
+            )
+        };
+        let mut lines = vec![
+            format!(
+                "counters {} {} {} {} {}",
+                self.box_entries_held,
+                self.box_blocked,
+                self.stuck_vehicles,
+                self.deadlocks,
+                self.spawned
+            ),
+            format!("max_queue{}", numbers(&self.max_queue)),
+            format!("max_approach_queue{}", numbers(&self.max_approach_queue)),
+        ];
+        let mut active: Vec<&VehicleRecord> = self.active.values().collect();
+        active.sort_by_key(|r| r.id);
+        lines.extend(active.into_iter().map(|r| format!("active {}", record(r))));
+        lines.extend(self.records.iter().map(|r| format!("record {}", record(r))));
+        lines.extend(self.signal_log.iter().map(|c| {
+            format!(
+                "signal {} {} {} {}",
+                c.frame,
+                direction_name(c.movement.approach),
+                turn_name(c.movement.turn),
+                light_state_name(c.state)
+            )
+        }));
+        lines
+    }
+
+    pub fn restore(&mut self, lines: &[&str]) -> Result<(), String> {
+        let mut restored = Statistics {
+            free_flow: self.free_flow,
+            ..Statistics::default()
+        };
+        let movement = |f: &mut Fields| -> Result<Movement, String> {
+            Ok(Movement {
+                approach: f.direction()?,
+                turn: f.turn()?,
+            })
+        };
+        for line in lines {
+            let mut f = Fields::new(line);
+            match f.word()? {
+                "counters" => {
+                    restored.box_entries_held = f.value()?;
+                    restored.box_blocked = f.value()?;
+                    restored.stuck_vehicles = f.value()?;
+                    restored.deadlocks = f.value()?;
+                    restored.spawned = f.value()?;
+                }
+                "max_queue" => {
+                    for q in restored.max_queue.iter_mut() {
+                        *q = f.value()?;
+                    }
+                }
+                "max_approach_queue" => {
+                    for q in restored.max_approach_queue.iter_mut() {
+                        *q = f.value()?;
+                    }
+                }
+                kind @ ("active" | "record") => {
+                    let record = VehicleRecord {
+                        id: f.value()?,
+                        movement: movement(&mut f)?,
+                        spawn_frame: f.value()?,
+                        exit_frame: f.optional()?,
+                        stopped_frames: f.value()?,
+                        delay_frames: f.value()?,
+                        removed: f.value()?,
+                    };
+                    if kind == "active" {
+                        restored.active.insert(record.id, record);
+                    } else {
+                        restored.records.push(record);
+                    }
+                }
+                // What each signal shows now is its last change
+                "signal" => {
+                    let change = SignalChange {
+                        frame: f.value()?,
+                        movement: movement(&mut f)?,
+                        state: f.light_state()?,
+                    };
+                    restored.signals[change.movement.index()] = Some(change.state);
+                    restored.signal_log.push(change);
+                }
+                other => return Err(format!("unknown stats item '{}'", other)),
+            }
+            f.end()?;
+        }
+        *self = restored;
+        Ok(())
+    }
+
+    // Plain-text table of the per-approach and per-movement aggregates
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<16} {:>10} {:>10} {:>10} {:>9}",
+            "", "throughput", "avg delay", "95% delay", "max queue"
+        );
+        let mut row = |label: String, s: &FlowSummary| {
+            let _ = writeln!(
+                out,
+                "{:<16} {:>10} {:>9.1}s {:>9.1}s {:>9}",
+                label, s.throughput, s.mean_delay, s.p95_delay, s.max_queue
+            );
+        };
+        for (approach, s) in self.by_approach() {
+            row(format!("{:?}bound", approach), &s);
+        }
+        for (m, s) in self.by_movement() {
+            row(format!("  {:?} {:?}", m.approach, m.turn), &s);
+        }
+        row("All".to_string(), &self.overall());
+        let _ = writeln!(
+            out,
+            "Spawned: {}, box entries held: {}, stops in box: {}, stuck: {}, deadlocks: {}",
+            self.spawned,
+            self.box_entries_held,
+            self.box_blocked,
+            self.stuck_vehicles,
+            self.deadlocks
+        );
+        out
+    }
+}
+
+// Stopped vehicles waiting to enter the box, per movement
+pub fn queues(vehicles: &[Vehicle]) -> [u32; MOVEMENT_COUNT] {
+    let mut queue = [0; MOVEMENT_COUNT];
+    for v in vehicles {
+        if v.stalled > 0 && !v.in_intersection {
+            queue[v.movement().index()] += 1;
+        }
+    }
+    queue
+}
+
+// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[u32], p: usize) -> Option<u32> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
@@ -0,0 +1,308 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::scenario::Scenario;
+use crate::simulation;
+
+// What each run reports, in the order of `Measures::values`
+pub const MEASURES: [&str; 7] = [
+    "throughput_per_hour",
+    "mean_delay_s",
+    "p95_delay_s",
+    "max_queue",
+    "spawned",
+    "stuck",
+    "deadlocks",
+];
+
+// One run's results
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Measures {
+    pub throughput_per_hour: f64,
+    pub mean_delay: f64, // seconds
+    pub p95_delay: f64,  // seconds
+    pub max_queue: u32,
+    pub spawned: u32,
+    pub stuck: u32,
+    pub deadlocks: u32,
+}
+
+impl Measures {
+    pub fn values(&self) -> [f64; 7] {
+        [
+            self.throughput_per_hour,
+            self.mean_delay,
+            self.p95_delay,
+            self.max_queue as f64,
+            self.spawned as f64,
+            self.stuck as f64,
+            self.deadlocks as f64,
+        ]
+    }
+}
+
+// Mean over the seeds with the half-width of its 95% confidence interval
+// (Student's t), None with a single run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub mean: f64,
+    pub ci95: Option<f64>,
+}
+
+// One combination of the varied settings
+#[derive(Clone, Debug)]
+pub struct Case {
+    pub settings: Vec<(String, String)>,
+    pub scenario: Scenario,
+}
+
+// A Monte Carlo parameter study: every combination of the varied settings
+// on top of a base scenario, each run once per seed. All cases use the same
+// seeds, so they see the same random arrivals as far as their settings allow
+// and differ only by what was varied.
+pub struct Sweep {
+    pub base: Scenario,
+    pub vary: Vec<(String, Vec<String>)>,
+    pub seeds: Vec<u64>,
+    pub duration: u32, // seconds
+}
+
+impl Sweep {
+    // Every combination, the first varied setting changing slowest. Each is
+    // checked, so a bad value fails before anything runs.
+    pub fn cases(&self) -> Result<Vec<Case>, String> {
+        let mut cases = vec![Case {
+            settings: Vec::new(),
+            scenario: self.base.clone(),
+        }];
+        for (name, values) in &self.vary {
+            let mut next = Vec::new();
+            for case in &cases {
+                for value in values {
+                    let mut case = case.clone();
+                    case.scenario.set(name, value)?;
+                    case.settings.push((name.clone(), value.clone()));
+                    next.push(case);
+                }
+            }
+            cases = next;
+        }
+        for case in &cases {
+            case.scenario.plan().map_err(|e| describe(case, &e))?;
+        }
+        Ok(cases)
+    }
+
+    // Run every case with every seed on `jobs` threads, returning each
+    // case's results in seed order
+    pub fn run(&self, jobs: usize) -> Result<Vec<(Case, Vec<Measures>)>, String> {
+        let cases = self.cases()?;
+        let runs: Vec<(usize, u64)> = (0..cases.len())
+            .flat_map(|c| self.seeds.iter().map(move |seed| (c, *seed)))
+            .collect();
+        let next = AtomicUsize::new(0);
+        let mut measured = vec![Measures::default(); runs.len()];
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs.max(1))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some((c, seed)) = runs.get(i) else {
+                                return Ok(done);
+                            };
+                            let result = run_once(&cases[*c].scenario, *seed, self.duration);
+                            done.push((i, result.map_err(|e| describe(&cases[*c], &e))?));
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                let done: Result<Vec<(usize, Measures)>, String> = worker
+                    .join()
+                    .map_err(|_| "a sweep worker panicked".to_string())?;
+                for (i, m) in done? {
+                    measured[i] = m;
+                }
+            }
+            Ok::<(), String>(())
+        })?;
+        let per_case = self.seeds.len();
+        Ok(cases
+            .into_iter()
+            .enumerate()
+            .map(|(c, case)| (case, measured[c * per_case..(c + 1) * per_case].to_vec()))
+            .collect())
+    }
+}
+
+fn describe(case: &Case, error: &str) -> String {
+    let settings: Vec<String> = case
+        .settings
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!("case {}: {}", settings.join(" "), error)
+}
+
+// One run of `scenario` with `seed` for `duration` seconds, stepped the
+// same way as a headless run
+pub fn run_once(scenario: &Scenario, seed: u64, duration: u32) -> Result<Measures, String> {
+    let mut sim = scenario.simulation(seed)?;
+    let frames = simulation::frames(duration).ok_or("the duration is too long")?;
+    while sim.frame() <= frames {
+        sim.step();
+        sim.watchdog_events.clear();
+    }
+    let overall = sim.stats.overall();
+    Ok(Measures {
+        throughput_per_hour: overall.throughput as f64 * 3600.0 / duration as f64,
+        mean_delay: overall.mean_delay,
+        p95_delay: overall.p95_delay,
+        max_queue: overall.max_queue,
+        spawned: sim.stats.spawned,
+        stuck: sim.stats.stuck_vehicles,
+        deadlocks: sim.stats.deadlocks,
+    })
+}
+
+pub fn estimate(samples: &[f64]) -> Estimate {
+    let n = samples.len();
+    let mean = samples.iter().sum::<f64>() / n.max(1) as f64;
+    let ci95 = (n > 1).then(|| {
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        t_975(n - 1) * (variance / n as f64).sqrt()
+    });
+    Estimate { mean, ci95 }
+}
+
+// Two-sided 95% critical value of Student's t with `df` degrees of freedom
+fn t_975(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match df {
+        0 => f64::NAN,
+        1..=30 => TABLE[df - 1],
+        31..=60 => 2.000,
+        61..=120 => 1.980,
+        _ => 1.960,
+    }
+}
+
+// `name=v1,v2,...`; commas inside `[...]` belong to the value, so list
+// settings can be varied too: `signals.splits=[2,1,1,1],[1,1,1,1]`
+pub fn parse_vary(arg: &str) -> Result<(String, Vec<String>), String> {
+    let (name, list) = arg.split_once('=').ok_or(format!(
+        "--vary expects name=value,value,..., got '{}'",
+        arg
+    ))?;
+    let mut values = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                values.push(list[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    values.push(list[start..].trim().to_string());
+    if values.iter().any(String::is_empty) {
+        return Err(format!("--vary {} has an empty value", name));
+    }
+    Ok((name.trim().to_string(), values))
+}
+
+// One row per case: the varied settings, the number of runs, then the mean
+// and 95% confidence half-width of every measure
+pub fn csv(vary: &[(String, Vec<String>)], results: &[(Case, Vec<Measures>)]) -> String {
+    let mut header: Vec<String> = vary.iter().map(|(name, _)| field(name)).collect();
+    header.push("runs".to_string());
+    for measure in MEASURES {
+        header.push(format!("{}_mean", measure));
+        header.push(format!("{}_ci95", measure));
+    }
+    let mut out = header.join(",") + "\n";
+    for (case, runs) in results {
+        let mut row: Vec<String> = case
+            .settings
+            .iter()
+            .map(|(_, value)| field(value))
+            .collect();
+        row.push(runs.len().to_string());
+        let values: Vec<[f64; 7]> = runs.iter().map(Measures::values).collect();
+        for k in 0..MEASURES.len() {
+            let e = estimate(&values.iter().map(|v| v[k]).collect::<Vec<_>>());
+            row.push(format!("{:.3}", e.mean));
+            row.push(e.ci95.map_or(String::new(), |ci| format!("{:.3}", ci)));
+        }
+        out += &(row.join(",") + "\n");
+    }
+    out
+}
+
+// A CSV field, quoted if it holds a comma or quote
+fn field(value: &str) -> String {
+    if value.contains([',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::FRAMES_PER_SECOND;
+    use std::path::Path;
+
+    #[test]
+    fn sweeps_the_cycle_of_a_scenario_timed_by_green() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/rush-hour.toml");
+        let sweep = Sweep {
+            base: Scenario::load(&path).unwrap(),
+            vary: vec![parse_vary("signals.cycle=40,80").unwrap()],
+            seeds: vec![1, 2],
+            duration: 60,
+        };
+        let results = sweep.run(2).unwrap();
+        let cycles: Vec<u32> = results
+            .iter()
+            .map(|(case, _)| case.scenario.plan().unwrap().unwrap().cycle_length())
+            .collect();
+        assert_eq!(cycles, [40 * FRAMES_PER_SECOND, 80 * FRAMES_PER_SECOND]);
+        assert!(results.iter().all(|(_, runs)| runs.len() == 2));
+    }
+
+    #[test]
+    fn sweeping_the_cycle_selects_the_fixed_controller() {
+        let sweep = Sweep {
+            base: Scenario::default(),
+            vary: vec![parse_vary("signals.cycle=40").unwrap()],
+            seeds: vec![1],
+            duration: 10,
+        };
+        assert!(sweep.run(1).is_ok());
+    }
+
+    #[test]
+    fn absurd_timings_fail_before_anything_runs() {
+        for vary in ["signals.cycle=40,4294967295", "signals.green=10,30000000"] {
+            let sweep = Sweep {
+                base: Scenario::default(),
+                vary: vec![parse_vary(vary).unwrap()],
+                seeds: vec![1],
+                duration: 10,
+            };
+            assert!(sweep.cases().is_err(), "{}", vary);
+            assert!(sweep.run(2).is_err(), "{}", vary);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use crate::simulation::{Checkpoint, FRAMES_PER_SECOND, Simulation};
+
+// How far back the viewer can rewind by default
+pub const DEFAULT_SECONDS: u32 = 30;
+
+// The last few seconds of a run, one checkpoint per frame, for scrubbing
+// back and forth. While scrubbing the live simulation is left alone;
+// `resume` then carries on from the frame being shown and forgets
+// everything after it.
+pub struct Timeline {
+    checkpoints: VecDeque<Checkpoint>,
+    capacity: usize,
+    cursor: Option<usize>, // checkpoint being shown, None when live
+}
+
+impl Timeline {
+    pub fn new(seconds: u32) -> Self {
+        let capacity = (seconds * FRAMES_PER_SECOND).max(1) as usize;
+        Timeline {
+            checkpoints: VecDeque::with_capacity(capacity),
+            capacity,
+            cursor: None,
+        }
+    }
+
+    // Remember the state after a live step, dropping the oldest when full
+    pub fn push(&mut self, sim: &Simulation) {
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(sim.checkpoint());
+    }
+
+    pub fn scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    // Move the view `frames` back, stopping at the oldest checkpoint
+    pub fn back(&mut self, frames: usize) {
+        if self.checkpoints.is_empty() {
+            return;
+        }
+        let newest = self.checkpoints.len() - 1;
+        self.cursor = Some(self.cursor.unwrap_or(newest).saturating_sub(frames));
+    }
+
+    // Move the view `frames` forward; going past the newest returns to live
+    pub fn forward(&mut self, frames: usize) {
+        if let Some(cursor) = self.cursor {
+            let target = cursor + frames;
+            self.cursor = (target < self.checkpoints.len() - 1).then_some(target);
+        }
+    }
+
+    // The state being shown while scrubbing
+    pub fn shown(&self) -> Option<&Simulation> {
+        self.cursor.map(|c| self.checkpoints[c].simulation())
+    }
+
+    // Rewind `sim` to the frame being shown and continue live from there
+    pub fn resume(&mut self, sim: &mut Simulation) {
+        if let Some(cursor) = self.cursor.take() {
+            sim.rewind(&self.checkpoints[cursor]);
+            self.checkpoints.truncate(cursor + 1);
+        }
+    }
+}
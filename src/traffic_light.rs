@@ -1,16 +1,34 @@
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use sdl2::rect::{Point, Rect};
 use sdl2::render::Canvas;
 use sdl2::video::Window;
+
+use crate::font;
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::vehicle::Direction;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LightState {
     Red,
+    Yellow,
     Green,
 }
+
+// What a head shows and how many frames until that changes, if known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indication {
+    pub state: LightState,
+    pub remaining: Option<u32>,
+}
+
 #[allow(dead_code)]
 pub struct TrafficLight {
     rect: Rect,
     state: LightState,
+    remaining: Option<u32>,
+    arrow: Option<Direction>,    // turn arrow pointing this way on screen
+    countdown_at: Option<Point>, // top-left of the countdown text
+    forced: bool,                // showing a manual override
 }
 
 impl TrafficLight {
@@ -18,25 +36,142 @@ impl TrafficLight {
         TrafficLight {
             rect: Rect::new(x, y, w, h),
             state,
+            remaining: None,
+            arrow: None,
+            countdown_at: None,
+            forced: false,
         }
     }
-    pub fn update(&mut self, is_green: bool) {
-        self.state = if is_green {
-            LightState::Green
-        } else {
-            LightState::Red
-        };
+    // A protected-turn arrow head
+    pub fn new_arrow(x: i32, y: i32, w: u32, h: u32, state: LightState, points: Direction) -> Self {
+        TrafficLight {
+            rect: Rect::new(x, y, w, h),
+            state,
+            remaining: None,
+            arrow: Some(points),
+            countdown_at: None,
+            forced: false,
+        }
+    }
+    // Where to write the seconds left in the current state
+    pub fn with_countdown_at(mut self, x: i32, y: i32) -> Self {
+        self.countdown_at = Some(Point::new(x, y));
+        self
+    }
+    pub fn update(&mut self, indication: Indication, forced: bool) {
+        self.state = indication.state;
+        self.remaining = indication.remaining;
+        self.forced = forced;
+    }
+    // Whether a click at `p` lands on this head
+    pub fn contains(&self, p: Point) -> bool {
+        self.rect.contains_point(p)
     }
-    pub fn draw(&self, canvas: &mut Canvas<Window>) {
-        let color = match self.state {
-            LightState::Red => Color::RGB(200, 0, 0),
-            LightState::Green => Color::RGB(0, 200, 0),
+    pub fn draw(&self, canvas: &mut Canvas<Window>, countdown: bool) {
+        let color = lit(self.state);
+        if countdown {
+            self.draw_countdown(canvas, color);
+        }
+        // Overridden heads get a white frame
+        if self.forced {
+            canvas.set_draw_color(Color::WHITE);
+            let r = self.rect;
+            let _ = canvas.draw_rect(Rect::new(
+                r.x() - 2,
+                r.y() - 2,
+                r.width() + 4,
+                r.height() + 4,
+            ));
+        }
+
+        let Some(points) = self.arrow else {
+            self.draw_three_aspect(canvas);
+            return;
         };
 
-        canvas.set_draw_color(color);
+        // Arrow heads: dark housing with a lit arrow
+        canvas.set_draw_color(Color::RGB(30, 30, 30));
         let _ = canvas.fill_rect(self.rect);
+        canvas.set_draw_color(color);
+        let c = self.rect.center();
+        let r = self.rect.width().min(self.rect.height()) as i32 / 2 - 3;
+        let (dx, dy) = match points {
+            Direction::North => (0, -1),
+            Direction::South => (0, 1),
+            Direction::East => (1, 0),
+            Direction::West => (-1, 0),
+        };
+        let tip = Point::new(c.x + dx * r, c.y + dy * r);
+        let tail = Point::new(c.x - dx * r, c.y - dy * r);
+        // Barbs start level with the centre, either side of the shaft
+        let barb1 = Point::new(c.x - dy * r / 2, c.y + dx * r / 2);
+        let barb2 = Point::new(c.x + dy * r / 2, c.y - dx * r / 2);
+        for w in -1..=1 {
+            let (ox, oy) = (dy.abs() * w, dx.abs() * w);
+            let _ = canvas.draw_line(tail.offset(ox, oy), tip.offset(ox, oy));
+            let _ = canvas.draw_line(barb1.offset(ox, oy), tip.offset(ox, oy));
+            let _ = canvas.draw_line(barb2.offset(ox, oy), tip.offset(ox, oy));
+        }
     }
     pub fn state(&self) -> LightState {
         self.state
     }
+
+    // Housing with red, amber and green lamps from top to bottom (left to
+    // right if the head is wider than tall), only the current one lit
+    fn draw_three_aspect(&self, canvas: &mut Canvas<Window>) {
+        canvas.set_draw_color(Color::RGB(30, 30, 30));
+        let _ = canvas.fill_rect(self.rect);
+        let vertical = self.rect.height() >= self.rect.width();
+        let (long, short) = if vertical {
+            (self.rect.height() as i32, self.rect.width() as i32)
+        } else {
+            (self.rect.width() as i32, self.rect.height() as i32)
+        };
+        let r = (short - 4).min(long / 3 - 2) / 2;
+        let states = [LightState::Red, LightState::Yellow, LightState::Green];
+        for (i, state) in states.into_iter().enumerate() {
+            let along = long * (2 * i as i32 + 1) / 6;
+            let c = if vertical {
+                Point::new(self.rect.x() + short / 2, self.rect.y() + along)
+            } else {
+                Point::new(self.rect.x() + along, self.rect.y() + short / 2)
+            };
+            let color = if state == self.state {
+                lit(state)
+            } else {
+                dim(state)
+            };
+            canvas.set_draw_color(color);
+            for dy in -r..=r {
+                let dx = ((r * r - dy * dy) as f64).sqrt() as i32;
+                let _ = canvas.draw_line(c.offset(-dx, dy), c.offset(dx, dy));
+            }
+        }
+    }
+
+    // Whole seconds left, rounded up, in the lamp's colour
+    fn draw_countdown(&self, canvas: &mut Canvas<Window>, color: Color) {
+        let (Some(at), Some(frames)) = (self.countdown_at, self.remaining) else {
+            return;
+        };
+        let seconds = frames.div_ceil(FRAMES_PER_SECOND);
+        font::draw_text(canvas, at.x, at.y, &seconds.to_string(), 2, color);
+    }
+}
+
+fn lit(state: LightState) -> Color {
+    match state {
+        LightState::Red => Color::RGB(200, 0, 0),
+        LightState::Yellow => Color::RGB(255, 170, 0),
+        LightState::Green => Color::RGB(0, 200, 0),
+    }
+}
+
+fn dim(state: LightState) -> Color {
+    match state {
+        LightState::Red => Color::RGB(60, 0, 0),
+        LightState::Yellow => Color::RGB(70, 45, 0),
+        LightState::Green => Color::RGB(0, 60, 0),
+    }
 }
@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::export::{direction_name, turn_name};
+use crate::vehicle::Vehicle;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrajectoryFormat {
+    Csv,
+    JsonLines,
+}
+
+impl TrajectoryFormat {
+    // `.jsonl`/`.ndjson` files get JSON Lines, anything else CSV
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl") | Some("ndjson") => TrajectoryFormat::JsonLines,
+            _ => TrajectoryFormat::Csv,
+        }
+    }
+}
+
+// Streams every vehicle's state to a file, one row per vehicle per sampled
+// frame. Columns: frame, id, x, y (rect centre), speed (px/frame),
+// direction, turn, in_intersection, has_turned.
+pub struct TrajectoryRecorder {
+    out: BufWriter<File>,
+    format: TrajectoryFormat,
+    every: u32,
+    // Bytes written so far, and where each sampled frame's rows start, so
+    // the file can be cut back when the run is rewound
+    written: u64,
+    starts: Vec<(i32, u64)>,
+}
+
+impl TrajectoryRecorder {
+    // Record every `every`-th frame (1 = all of them)
+    pub fn create(path: &Path, every: u32) -> io::Result<Self> {
+        let format = TrajectoryFormat::from_path(path);
+        let mut recorder = TrajectoryRecorder {
+            out: BufWriter::new(File::create(path)?),
+            format,
+            every: every.max(1),
+            written: 0,
+            starts: Vec::new(),
+        };
+        if format == TrajectoryFormat::Csv {
+            recorder.write(
+                "frame,id,x,y,speed,direction,turn,in_intersection,has_turned\n".to_string(),
+            )?;
+        }
+        Ok(recorder)
+    }
+
+    fn write(&mut self, text: String) -> io::Result<()> {
+        self.out.write_all(text.as_bytes())?;
+        self.written += text.len() as u64;
+        Ok(())
+    }
+
+    pub fn record(&mut self, frame: i32, vehicles: &[Vehicle]) -> io::Result<()> {
+        if (frame - 1).rem_euclid(self.every as i32) != 0 {
+            return Ok(());
+        }
+        self.starts.push((frame, self.written));
+        for v in vehicles {
+            let c = v.rect().center();
+            let direction = direction_name(v.direction());
+            let turn = turn_name(v.movement().turn);
+            let row = match self.format {
+                TrajectoryFormat::Csv => format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    frame,
+                    v.id(),
+                    c.x,
+                    c.y,
+                    v.speed(),
+                    direction,
+                    turn,
+                    v.in_intersection,
+                    v.has_turned
+                ),
+                TrajectoryFormat::JsonLines => format!(
+                    "{{\"frame\":{},\"id\":{},\"x\":{},\"y\":{},\"speed\":{},\"direction\":\"{}\",\"turn\":\"{}\",\"in_intersection\":{},\"has_turned\":{}}}\n",
+                    frame,
+                    v.id(),
+                    c.x,
+                    c.y,
+                    v.speed(),
+                    direction,
+                    turn,
+                    v.in_intersection,
+                    v.has_turned
+                ),
+            };
+            self.write(row)?;
+        }
+        Ok(())
+    }
+
+    // Drop the rows of `frame` and after, once the run has gone back to it
+    pub fn truncate(&mut self, frame: i32) -> io::Result<()> {
+        let Some(k) = self.starts.iter().position(|(f, _)| *f >= frame) else {
+            return Ok(());
+        };
+        let offset = self.starts[k].1;
+        self.starts.truncate(k);
+        self.out.flush()?;
+        let file = self.out.get_mut();
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        self.written = offset;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vehicle::{Direction, Turn};
+    use std::fs;
+
+    #[test]
+    fn truncating_drops_the_abandoned_rows() {
+        let path = std::env::temp_dir().join(format!("trajectory-{}.csv", std::process::id()));
+        let vehicles = [
+            Vehicle::with_turn(1, Direction::North, Turn::Left),
+            Vehicle::with_turn(2, Direction::East, Turn::Straight),
+        ];
+        let mut recorder = TrajectoryRecorder::create(&path, 2).unwrap();
+        for frame in 1..=9 {
+            recorder.record(frame, &vehicles).unwrap();
+        }
+        // Rewound to frame 4, then run on to 7
+        recorder.truncate(4).unwrap();
+        for frame in 4..=7 {
+            recorder.record(frame, &vehicles).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let frames: Vec<&str> = text
+            .lines()
+            .skip(1)
+            .map(|l| l.split(',').next().unwrap())
+            .collect();
+        assert_eq!(frames, ["1", "1", "3", "3", "5", "5", "7", "7"]);
+    }
+}
@@ -1,11 +1,18 @@
 use rand::Rng;
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use sdl2::rect::{Point, Rect};
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
+use crate::conflict::Movement;
+use crate::export::{direction_name, hold_from_name, hold_name, turn_name};
+use crate::intersection::{self, BOX_MAX, BOX_MIN, CENTER, LANE_OFFSET, ROAD_WIDTH};
+use crate::snapshot::{self, Fields};
 use crate::traffic_light::LightState;
 
+// Pixels a vehicle moves each frame it isn't held
+pub const SPEED: i32 = 5;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     North,
@@ -19,41 +26,65 @@ pub enum Turn {
     Right,
     Left,
 }
+// Why a vehicle didn't move last frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hold {
+    Light,     // stopped at a red light
+    Following, // closer than the safe distance to the vehicle ahead
+    Exit,      // waiting at the line for room on its exit
+    Conflict,  // yielding right-of-way, or would touch another vehicle
+}
 #[derive(Clone)]
 pub struct Vehicle {
+    id: u32,
     pub rect: Rect,
     direction: Direction,
+    origin: Direction,
     velocity: i32,
     color: Color,
     turn: Turn,
     pub has_turned: bool,
     pub in_intersection: bool,
+    pub stalled: u32,            // frames since this vehicle last moved
+    pub held_for_exit: bool,     // waiting at the line for room on its exit
+    pub blocked_by: Option<u32>, // id of the vehicle that held it back last frame
+    pub held: Option<Hold>,      // why it didn't move last frame
 }
 
 impl Vehicle {
-    pub fn new(direction: Direction) -> Self {
-        let velocity = 5;
-        let rect = match direction {
-            Direction::North => Rect::new(415, 800, 20, 40),
-            Direction::South => Rect::new(365, -40, 20, 40),
-            Direction::East => Rect::new(-40, 415, 40, 20),
-            Direction::West => Rect::new(800, 365, 40, 20),
-        };
-        let mut rng = rand::rng();
-        let (turn, color) = match rng.random_range(0..3) {
-            0 => (Turn::Straight, Color::CYAN),
-            1 => (Turn::Right, Color::YELLOW),
-            2 => (Turn::Left, Color::BLUE),
+    // A vehicle with a random turn drawn from `rng`
+    pub fn new(id: u32, direction: Direction, rng: &mut impl Rng) -> Self {
+        let turn = match rng.random_range(0..3) {
+            0 => Turn::Straight,
+            1 => Turn::Right,
+            2 => Turn::Left,
             _ => unreachable!(),
         };
+        Vehicle::with_turn(id, direction, turn)
+    }
+
+    pub fn with_turn(id: u32, direction: Direction, turn: Turn) -> Self {
+        let velocity = SPEED;
+        let rect = spawn_rect(direction);
+        let color = match turn {
+            Turn::Straight => Color::CYAN,
+            Turn::Right => Color::YELLOW,
+            Turn::Left => Color::BLUE,
+        };
         Vehicle {
+            id,
             rect,
             direction,
+            origin: direction,
             velocity,
             color,
             turn,
             has_turned: false,
             in_intersection: false,
+            stalled: 0,
+            held_for_exit: false,
+            blocked_by: None,
+            held: None,
         }
     }
 
@@ -64,12 +95,12 @@ impl Vehicle {
             return;
         }
         if !self.has_turned && !self.in_intersection {
-            // Detect entry into intersection bounds (e.g., 350 <= x/y <= 450)
+            // Detect entry into intersection bounds (BOX_MIN <= x/y <= BOX_MAX)
             let in_intersection = match self.direction {
-                Direction::North => self.rect.y() <= 450,
-                Direction::South => self.rect.y() + self.rect.height() as i32 >= 350,
-                Direction::East => self.rect.x() + self.rect.width() as i32 >= 350,
-                Direction::West => self.rect.x() <= 450,
+                Direction::North => self.rect.y() <= BOX_MAX,
+                Direction::South => self.rect.y() + self.rect.height() as i32 >= BOX_MIN,
+                Direction::East => self.rect.x() + self.rect.width() as i32 >= BOX_MIN,
+                Direction::West => self.rect.x() <= BOX_MAX,
             };
 
             if in_intersection {
@@ -77,27 +108,8 @@ impl Vehicle {
             }
         }
         if !self.has_turned {
-            let turn_point = match (self.direction, self.turn) {
-                // Left turns
-                (Direction::West, Turn::Left) => (355, 375), // West→South
-                (Direction::North, Turn::Left) => (425, 355), // North→West
-                (Direction::South, Turn::Left) => (375, 445), // South→East
-                (Direction::East, Turn::Left) => (445, 425), // East→North
-
-                // Right turns
-                (Direction::West, Turn::Right) => (405, 375), // West→North
-                (Direction::North, Turn::Right) => (420, 405), // North→East
-                (Direction::South, Turn::Right) => (365, 395), // South→West
-                (Direction::East, Turn::Right) => (395, 415), // East→South
-
-                // Straight - use center of intersection
-                (Direction::West, Turn::Straight) => (400, 400),
-                (Direction::East, Turn::Straight) => (400, 400),
-                (Direction::North, Turn::Straight) => (400, 400),
-                (Direction::South, Turn::Straight) => (400, 400),
-            };
-
-            let point_rect = Rect::new(turn_point.0, turn_point.1, 1, 1);
+            let turn_point = turn_point(self.direction, self.turn);
+            let point_rect = Rect::new(turn_point.x, turn_point.y, 1, 1);
             if self.rect.has_intersection(point_rect) {
                 self.apply_turn();
                 self.has_turned = true; // Don't forget to mark as turned
@@ -117,6 +129,10 @@ impl Vehicle {
         canvas.set_draw_color(self.color);
         let _ = canvas.fill_rect(self.rect);
     }
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
     // Get the vehicle's direction
     pub fn direction(&self) -> Direction {
         self.direction
@@ -127,6 +143,73 @@ impl Vehicle {
         self.rect
     }
 
+    // Pixels moved last frame: the velocity, or 0 if it was held back
+    pub fn speed(&self) -> i32 {
+        if self.stalled == 0 { self.velocity } else { 0 }
+    }
+
+    // The movement (approach heading + turn) this vehicle makes through the box
+    pub fn movement(&self) -> Movement {
+        Movement {
+            approach: self.origin,
+            turn: self.turn,
+        }
+    }
+
+    // Every field on one line, for simulation snapshots:
+    // id x y w h direction origin velocity r g b turn has_turned
+    // in_intersection stalled held_for_exit blocked_by held
+    pub fn snapshot_line(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            self.id,
+            self.rect.x(),
+            self.rect.y(),
+            self.rect.width(),
+            self.rect.height(),
+            direction_name(self.direction),
+            direction_name(self.origin),
+            self.velocity,
+            self.color.r,
+            self.color.g,
+            self.color.b,
+            turn_name(self.turn),
+            self.has_turned,
+            self.in_intersection,
+            self.stalled,
+            self.held_for_exit,
+            snapshot::optional(self.blocked_by),
+            self.held.map_or("-", hold_name)
+        )
+    }
+
+    pub fn from_snapshot_line(line: &str) -> Result<Self, String> {
+        let mut f = Fields::new(line);
+        let vehicle = Vehicle {
+            id: f.value()?,
+            rect: Rect::new(f.value()?, f.value()?, f.value()?, f.value()?),
+            direction: f.direction()?,
+            origin: f.direction()?,
+            velocity: f.value()?,
+            color: Color::RGB(f.value()?, f.value()?, f.value()?),
+            turn: f.turn()?,
+            has_turned: f.value()?,
+            in_intersection: f.value()?,
+            stalled: f.value()?,
+            held_for_exit: f.value()?,
+            blocked_by: f.optional()?,
+            held: match f.word()? {
+                "-" => None,
+                word => Some(
+                    hold_from_name(word)
+                        .ok_or_else(|| format!("no hold '{}' in '{}'", word, line))?,
+                ),
+            },
+        };
+        f.end()?;
+        Ok(vehicle)
+    }
+
     fn should_stop_at_light(&self, light_state: LightState) -> bool {
         if self.in_intersection || self.has_turned {
             return false;
@@ -135,37 +218,34 @@ impl Vehicle {
             return false;
         }
 
-        // Calculate distance to stop line
+        // Stop while the front edge is in this approach's stop zone
+        stop_zone(self.direction).contains_point(self.front())
+    }
+
+    // Middle of the leading edge
+    pub fn front(&self) -> Point {
+        let (r, c) = (self.rect, self.rect.center());
+        match self.direction {
+            Direction::North => Point::new(c.x, r.y()),
+            Direction::South => Point::new(c.x, r.bottom()),
+            Direction::East => Point::new(r.right(), c.y),
+            Direction::West => Point::new(r.x(), c.y),
+        }
+    }
+
+    // The stretch of lane ahead checked against the safe following distance
+    pub fn look_ahead(&self, distance: u32) -> Rect {
+        let r = self.rect;
+        let d = distance as i32;
         match self.direction {
-            Direction::North => self.rect.y() > 350 && self.rect.y() <= 450,
-            Direction::South => {
-                (self.rect.y() + self.rect.height() as i32) < 450
-                    && (self.rect.y() + self.rect.height() as i32) >= 350
-            }
-            Direction::East => {
-                (self.rect.x() + self.rect.width() as i32) < 450
-                    && (self.rect.x() + self.rect.width() as i32) >= 350
-            }
-            Direction::West => self.rect.x() > 350 && self.rect.x() <= 450,
+            Direction::North => Rect::new(r.x(), r.y() - d, r.width(), distance),
+            Direction::South => Rect::new(r.x(), r.bottom(), r.width(), distance),
+            Direction::East => Rect::new(r.right(), r.y(), distance, r.height()),
+            Direction::West => Rect::new(r.x() - d, r.y(), distance, r.height()),
         }
     }
     pub fn apply_turn(&mut self) {
-        self.direction = match (self.direction, self.turn) {
-            // Go straight: no change
-            (dir, Turn::Straight) => dir,
-
-            // Right turns
-            (Direction::North, Turn::Right) => Direction::East,
-            (Direction::East, Turn::Right) => Direction::South,
-            (Direction::South, Turn::Right) => Direction::West,
-            (Direction::West, Turn::Right) => Direction::North,
-
-            // Left turns
-            (Direction::North, Turn::Left) => Direction::West,
-            (Direction::West, Turn::Left) => Direction::South,
-            (Direction::South, Turn::Left) => Direction::East,
-            (Direction::East, Turn::Left) => Direction::North,
-        };
+        self.direction = turned(self.direction, self.turn);
         // 2) Swap width/height if we flipped between vertical<->horizontal
         let (w, h) = (self.rect.width(), self.rect.height());
         if (w > h && matches!(self.direction, Direction::North | Direction::South))
@@ -185,3 +265,74 @@ impl Vehicle {
         self.has_turned = true;
     }
 }
+
+// Heading after making `turn` from `direction`
+pub fn turned(direction: Direction, turn: Turn) -> Direction {
+    match (direction, turn) {
+        // Go straight: no change
+        (dir, Turn::Straight) => dir,
+
+        // Right turns
+        (Direction::North, Turn::Right) => Direction::East,
+        (Direction::East, Turn::Right) => Direction::South,
+        (Direction::South, Turn::Right) => Direction::West,
+        (Direction::West, Turn::Right) => Direction::North,
+
+        // Left turns
+        (Direction::North, Turn::Left) => Direction::West,
+        (Direction::West, Turn::Left) => Direction::South,
+        (Direction::South, Turn::Left) => Direction::East,
+        (Direction::East, Turn::Left) => Direction::North,
+    }
+}
+
+// Where a vehicle switches to its exit heading: it turns on the first frame
+// its rect covers this point
+pub fn turn_point(direction: Direction, turn: Turn) -> Point {
+    // Lane centre lines, to the right and left of the middle
+    let (c, hi, lo) = (CENTER, CENTER + LANE_OFFSET, CENTER - LANE_OFFSET);
+    let (x, y) = match (direction, turn) {
+        // Left turns, just inside the far side of the box
+        (Direction::West, Turn::Left) => (BOX_MIN + 5, lo), // West→South
+        (Direction::North, Turn::Left) => (hi, BOX_MIN + 5), // North→West
+        (Direction::South, Turn::Left) => (lo, BOX_MAX - 5), // South→East
+        (Direction::East, Turn::Left) => (BOX_MAX - 5, hi), // East→North
+
+        // Right turns, just before the middle
+        (Direction::West, Turn::Right) => (c + 5, lo), // West→North
+        (Direction::North, Turn::Right) => (hi - 5, c + 5), // North→East
+        (Direction::South, Turn::Right) => (lo - 10, c - 5), // South→West
+        (Direction::East, Turn::Right) => (c - 5, hi - 10), // East→South
+
+        // Straight - use center of intersection
+        (_, Turn::Straight) => (c, c),
+    };
+    Point::new(x, y)
+}
+
+// Where a vehicle stops for a red light: it holds while its front edge is in
+// this stretch of its lane, which spans the box
+pub fn stop_zone(direction: Direction) -> Rect {
+    // A vehicle's width either side of its lane's centre line
+    let (hi, lo) = (CENTER + LANE_OFFSET - 10, CENTER - LANE_OFFSET - 10);
+    let across = ROAD_WIDTH;
+    match direction {
+        Direction::North => Rect::new(hi, BOX_MIN + 1, 20, across),
+        Direction::South => Rect::new(lo, BOX_MIN, 20, across),
+        Direction::East => Rect::new(BOX_MIN, hi, across, 20),
+        Direction::West => Rect::new(BOX_MIN + 1, lo, across, 20),
+    }
+}
+
+// Where a vehicle heading `direction` enters, just off the edge of the world
+// in its lane, 15px right of the road's centre line
+pub fn spawn_rect(direction: Direction) -> Rect {
+    let world = intersection::world_bounds();
+    let c = world.center();
+    match direction {
+        Direction::North => Rect::new(c.x + 15, world.bottom(), 20, 40),
+        Direction::South => Rect::new(c.x - 35, world.y() - 40, 20, 40),
+        Direction::East => Rect::new(world.x() - 40, c.y + 15, 40, 20),
+        Direction::West => Rect::new(world.right(), c.y - 35, 40, 20),
+    }
+}
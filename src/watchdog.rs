@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl2::rect::Rect;
+
+use crate::snapshot::Fields;
+use crate::vehicle::Vehicle;
+
+// What to do once a circular wait has been found
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Report,    // only report it
+    RemoveOne, // take the longest-stuck vehicle of the cycle off the road
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchdogConfig {
+    pub stuck_after: u32, // frames without moving before a vehicle counts as stuck
+    pub resolution: Resolution,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            stuck_after: 1200, // 20 s at 60 fps, longer than any red
+            resolution: Resolution::Report,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StuckVehicle {
+    pub id: u32,
+    pub rect: Rect,
+    pub stalled: u32,
+    pub blocked_by: Option<u32>,
+}
+
+impl StuckVehicle {
+    fn of(v: &Vehicle) -> Self {
+        StuckVehicle {
+            id: v.id(),
+            rect: v.rect(),
+            stalled: v.stalled,
+            blocked_by: v.blocked_by,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum WatchdogEvent {
+    Stuck {
+        frame: i32,
+        vehicle: StuckVehicle,
+    },
+    // Vehicles each waiting on the next, the last on the first
+    Deadlock {
+        frame: i32,
+        cycle: Vec<StuckVehicle>,
+    },
+    Removed {
+        frame: i32,
+        id: u32,
+    },
+}
+
+impl std::fmt::Display for WatchdogEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let describe = |v: &StuckVehicle| {
+            format!(
+                "#{} at ({}, {}) still for {} frames",
+                v.id,
+                v.rect.x(),
+                v.rect.y(),
+                v.stalled
+            )
+        };
+        match self {
+            WatchdogEvent::Stuck { frame, vehicle } => {
+                write!(f, "[frame {}] stuck: {}", frame, describe(vehicle))?;
+                match vehicle.blocked_by {
+                    Some(id) => write!(f, ", waiting on #{}", id),
+                    None => Ok(()),
+                }
+            }
+            WatchdogEvent::Deadlock { frame, cycle } => {
+                let members: Vec<String> = cycle.iter().map(describe).collect();
+                write!(f, "[frame {}] deadlock: {}", frame, members.join(" -> "))
+            }
+            WatchdogEvent::Removed { frame, id } => {
+                write!(f, "[frame {}] removed #{} to break a deadlock", frame, id)
+            }
+        }
+    }
+}
+
+// Watches for vehicles that stop moving and for cycles in the "waits on"
+// graph built from each vehicle's `blocked_by`
+#[derive(Clone)]
+pub struct Watchdog {
+    pub config: WatchdogConfig,
+    reported: HashSet<u32>,
+    reported_cycles: HashSet<Vec<u32>>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Watchdog {
+            config,
+            reported: HashSet::new(),
+            reported_cycles: HashSet::new(),
+        }
+    }
+
+    // What has been reported so far, for simulation snapshots:
+    // `reported ID...` and one `cycle ID...` line per reported deadlock
+    pub fn snapshot_lines(&self) -> Vec<String> {
+        let mut reported: Vec<u32> = self.reported.iter().copied().collect();
+        reported.sort_unstable();
+        let mut cycles: Vec<&Vec<u32>> = self.reported_cycles.iter().collect();
+        cycles.sort();
+        let ids = |ids: &[u32]| ids.iter().map(|id| format!(" {}", id)).collect::<String>();
+        let mut lines = vec![format!("reported{}", ids(&reported))];
+        lines.extend(cycles.iter().map(|c| format!("cycle{}", ids(c))));
+        lines
+    }
+
+    pub fn restore(&mut self, lines: &[&str]) -> Result<(), String> {
+        let mut reported = HashSet::new();
+        let mut reported_cycles = HashSet::new();
+        for line in lines {
+            let mut f = Fields::new(line);
+            match f.word()? {
+                "reported" => reported.extend(f.rest::<u32>()?),
+                "cycle" => {
+                    reported_cycles.insert(f.rest()?);
+                }
+                other => return Err(format!("unknown watchdog item '{}'", other)),
+            }
+        }
+        self.reported = reported;
+        self.reported_cycles = reported_cycles;
+        Ok(())
+    }
+
+    // Vehicles that have been still for at least `stuck_after` frames
+    pub fn stuck<'a>(&self, vehicles: &'a [Vehicle]) -> Vec<&'a Vehicle> {
+        vehicles
+            .iter()
+            .filter(|v| v.stalled >= self.config.stuck_after)
+            .collect()
+    }
+
+    // New stuck vehicles and deadlocks since the last call. Each is reported
+    // once until it clears.
+    pub fn check(&mut self, vehicles: &[Vehicle], frame: i32) -> Vec<WatchdogEvent> {
+        let stuck = self.stuck(vehicles);
+        let mut events = Vec::new();
+
+        let ids: HashSet<u32> = stuck.iter().map(|v| v.id()).collect();
+        self.reported.retain(|id| ids.contains(id));
+        for v in &stuck {
+            if self.reported.insert(v.id()) {
+                events.push(WatchdogEvent::Stuck {
+                    frame,
+                    vehicle: StuckVehicle::of(v),
+                });
+            }
+        }
+
+        let cycles = find_cycles(&stuck);
+        self.reported_cycles.retain(|c| cycles.contains(c));
+        let by_id: HashMap<u32, &Vehicle> = stuck.iter().map(|v| (v.id(), *v)).collect();
+        for cycle in cycles {
+            if !self.reported_cycles.insert(cycle.clone()) {
+                continue;
+            }
+            let members: Vec<StuckVehicle> =
+                cycle.iter().map(|id| StuckVehicle::of(by_id[id])).collect();
+            let victim = members
+                .iter()
+                .max_by_key(|v| (v.stalled, std::cmp::Reverse(v.id)))
+                .map(|v| v.id);
+            events.push(WatchdogEvent::Deadlock {
+                frame,
+                cycle: members,
+            });
+            if self.config.resolution == Resolution::RemoveOne
+                && let Some(id) = victim
+            {
+                events.push(WatchdogEvent::Removed { frame, id });
+            }
+        }
+        events
+    }
+}
+
+// Cycles among stuck vehicles, each rotated to start at its smallest id
+fn find_cycles(stuck: &[&Vehicle]) -> Vec<Vec<u32>> {
+    let waits_on: HashMap<u32, u32> = stuck
+        .iter()
+        .filter_map(|v| v.blocked_by.map(|b| (v.id(), b)))
+        .collect();
+    let mut done: HashSet<u32> = HashSet::new();
+    let mut cycles = Vec::new();
+
+    for v in stuck {
+        let mut path: Vec<u32> = Vec::new();
+        let mut at = v.id();
+        // Walk the chain until it leaves the stuck set, hits explored ground
+        // or comes back on itself
+        while !done.contains(&at) {
+            if let Some(pos) = path.iter().position(|id| *id == at) {
+                let mut cycle = path[pos..].to_vec();
+                let start = cycle
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, id)| **id)
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
+                cycle.rotate_left(start);
+                cycles.push(cycle);
+                break;
+            }
+            path.push(at);
+            match waits_on.get(&at) {
+                Some(next) => at = *next,
+                None => break,
+            }
+        }
+        done.extend(path);
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Scenario;
+    use crate::simulation::Simulation;
+    use crate::vehicle::{Direction, Turn};
+
+    // Three vehicles each waiting on the next, the first still the longest
+    fn deadlock() -> Vec<Vehicle> {
+        let mut vehicles: Vec<Vehicle> = [Direction::North, Direction::East, Direction::South]
+            .into_iter()
+            .enumerate()
+            .map(|(i, d)| Vehicle::with_turn(i as u32 + 1, d, Turn::Left))
+            .collect();
+        for (i, v) in vehicles.iter_mut().enumerate() {
+            v.stalled = 1500 - i as u32;
+            v.blocked_by = Some((i as u32 + 1) % 3 + 1);
+        }
+        vehicles
+    }
+
+    #[test]
+    fn report_only_reports_a_deadlock() {
+        let mut watchdog = Watchdog::new(WatchdogConfig::default());
+        let events = watchdog.check(&deadlock(), 10);
+        assert!(
+            matches!(&events[..], [.., WatchdogEvent::Deadlock { cycle, .. }] if cycle.len() == 3)
+        );
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, WatchdogEvent::Removed { .. }))
+        );
+    }
+
+    #[test]
+    fn remove_one_clears_a_deadlock() {
+        let scenario =
+            Scenario::parse("[watchdog]\nstuck_after = 20\nresolution = \"remove\"\n").unwrap();
+        let mut sim = Simulation::with_seed(scenario.controller().unwrap(), 1);
+        scenario.set_up(&mut sim);
+        assert_eq!(sim.watchdog.config.resolution, Resolution::RemoveOne);
+        assert_eq!(sim.watchdog.config.stuck_after, 1200);
+
+        let mut vehicles = deadlock();
+        let events = sim.watchdog.check(&vehicles, 10);
+        let removed: Vec<u32> = events
+            .iter()
+            .filter_map(|e| match e {
+                WatchdogEvent::Removed { id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(removed, [1]);
+
+        vehicles.retain(|v| v.id() != 1);
+        let events = sim.watchdog.check(&vehicles, 11);
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, WatchdogEvent::Deadlock { .. }))
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs;
+use std::path::Path;
+
+use crate::conflict::{MOVEMENT_COUNT, Movement};
+use crate::export::{direction_from_name, turn_from_name};
+use crate::signal::SignalPlan;
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::stats::APPROACHES;
+use crate::vehicle::Turn;
+
+// Vehicles per hour of green one lane discharges, unless a scenario or
+// `--saturation` says otherwise: the HCM base saturation flow
+pub const SATURATION: f64 = 1900.0;
+
+// Demand and capacity per movement, by movement index, in vehicles per hour
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flows {
+    pub volume: [f64; MOVEMENT_COUNT],
+    pub saturation: [f64; MOVEMENT_COUNT],
+}
+
+impl Flows {
+    // Approach rates in `APPROACHES` order, shared equally between the three
+    // turns as vehicles pick them
+    pub fn from_demand(rates: [f64; 4], saturation: f64) -> Self {
+        let mut volume = [0.0; MOVEMENT_COUNT];
+        for m in Movement::all() {
+            let a = APPROACHES
+                .iter()
+                .position(|d| *d == m.approach)
+                .unwrap_or(0);
+            volume[m.index()] = rates[a] / 3.0;
+        }
+        Flows {
+            volume,
+            saturation: [saturation; MOVEMENT_COUNT],
+        }
+    }
+
+    // A flows file, one movement per line with its volume and optionally its
+    // own saturation flow; movements left out have no demand:
+    //
+    //   # approach turn veh/h [saturation veh/h]
+    //   north straight 400
+    //   north left 120 1500
+    pub fn parse(text: &str, saturation: f64) -> Result<Self, String> {
+        let mut flows = Flows {
+            volume: [0.0; MOVEMENT_COUNT],
+            saturation: [saturation; MOVEMENT_COUNT],
+        };
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = || format!("line {}: can't read '{}'", n + 1, line);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (approach, turn, volume, own) = match words.as_slice() {
+                [a, t, v] => (a, t, v, None),
+                [a, t, v, s] => (a, t, v, Some(s)),
+                _ => return Err(bad()),
+            };
+            let m = Movement {
+                approach: direction_from_name(approach).ok_or_else(bad)?,
+                turn: turn_from_name(turn).ok_or_else(bad)?,
+            };
+            flows.volume[m.index()] = rate(volume).ok_or_else(bad)?;
+            if let Some(s) = own {
+                flows.saturation[m.index()] = rate(s).filter(|s| *s > 0.0).ok_or_else(bad)?;
+            }
+        }
+        Ok(flows)
+    }
+
+    // Arrivals measured in an exported `vehicles.csv`: vehicles spawned per
+    // movement over the time between the first and last spawn
+    pub fn measured(csv: &str, saturation: f64) -> Result<Self, String> {
+        let mut lines = csv.lines();
+        let header: Vec<&str> = lines.next().unwrap_or("").split(',').collect();
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|h| *h == name)
+                .ok_or(format!("no '{}' column, is this a vehicles.csv?", name))
+        };
+        let (approach, turn, spawn) =
+            (column("approach")?, column("turn")?, column("spawn_frame")?);
+        let mut counts = [0u32; MOVEMENT_COUNT];
+        let (mut first, mut last) = (i64::MAX, i64::MIN);
+        for (n, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split(',').collect();
+            let bad = || format!("row {}: can't read '{}'", n + 1, line);
+            let get = |k: usize| fields.get(k).copied().ok_or_else(bad);
+            let m = Movement {
+                approach: direction_from_name(get(approach)?).ok_or_else(bad)?,
+                turn: turn_from_name(get(turn)?).ok_or_else(bad)?,
+            };
+            let frame: i64 = get(spawn)?.parse().map_err(|_| bad())?;
+            counts[m.index()] += 1;
+            (first, last) = (first.min(frame), last.max(frame));
+        }
+        if last <= first {
+            return Err("need at least two vehicles spawned at different times".to_string());
+        }
+        let hours = (last - first) as f64 / FRAMES_PER_SECOND as f64 / 3600.0;
+        Ok(Flows {
+            volume: counts.map(|c| c as f64 / hours),
+            saturation: [saturation; MOVEMENT_COUNT],
+        })
+    }
+
+    pub fn load(path: &Path, saturation: f64) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Flows::parse(&text, saturation).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Flow ratio q/s of one movement
+    fn ratio(&self, m: Movement) -> f64 {
+        self.volume[m.index()] / self.saturation[m.index()]
+    }
+}
+
+// Limits on the timing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub lost_time: f64, // seconds lost per phase to start-up
+    pub min_cycle: f64,
+    pub max_cycle: f64,
+    pub min_green: f64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            lost_time: 2.0,
+            min_cycle: 30.0,
+            max_cycle: 120.0,
+            min_green: 5.0,
+        }
+    }
+}
+
+// Webster's timing for a plan: the critical flow ratio of each phase, their
+// sum Y, the optimal cycle before clamping and the plan with its new greens
+#[derive(Clone, Debug)]
+pub struct Timing {
+    pub critical: Vec<f64>,
+    pub total: f64,
+    pub optimal_cycle: f64, // seconds
+    pub plan: SignalPlan,
+}
+
+impl Timing {
+    pub fn cycle(&self) -> f64 {
+        self.plan.cycle_length() as f64 / FRAMES_PER_SECOND as f64
+    }
+}
+
+// Critical flow ratio of each phase. Each approach has one lane shared by
+// its turns, so an approach flows freely only in phases that green all
+// three of them; its whole ratio is shared between those. A movement of an
+// approach that is never fully green counts on its own, shared between the
+// phases that serve it.
+pub fn critical_ratios(plan: &SignalPlan, flows: &Flows) -> Vec<f64> {
+    let mut critical = vec![0.0f64; plan.phases.len()];
+    for approach in APPROACHES {
+        let turns =
+            [Turn::Straight, Turn::Right, Turn::Left].map(|turn| Movement { approach, turn });
+        let full: Vec<usize> = (0..plan.phases.len())
+            .filter(|p| turns.iter().all(|m| plan.phases[*p].movements.contains(m)))
+            .collect();
+        if !full.is_empty() {
+            let y = turns.iter().map(|m| flows.ratio(*m)).sum::<f64>() / full.len() as f64;
+            for p in full {
+                critical[p] = critical[p].max(y);
+            }
+            continue;
+        }
+        for m in turns {
+            let serving: Vec<usize> = (0..plan.phases.len())
+                .filter(|p| plan.phases[*p].movements.contains(&m))
+                .collect();
+            for p in &serving {
+                critical[*p] = critical[*p].max(flows.ratio(m) / serving.len() as f64);
+            }
+        }
+    }
+    critical
+}
+
+// Webster's method on the phases of `plan`: cycle C = (1.5 L + 5) / (1 - Y)
+// with L the lost time of all phases and Y the sum of the critical flow
+// ratios, clamped to the limits; the effective green C - L is then shared in
+// proportion to each phase's ratio. Fixed plans have no amber, so each
+// phase's green is its effective green plus its lost time, and at least the
+// minimum green.
+pub fn optimise(plan: &SignalPlan, flows: &Flows, limits: &Limits) -> Result<Timing, String> {
+    let critical = critical_ratios(plan, flows);
+    let total: f64 = critical.iter().sum();
+    if total >= 1.0 {
+        return Err(format!(
+            "demand is over capacity (Y = {:.2}), no cycle can serve it",
+            total
+        ));
+    }
+    let phases = plan.phases.len() as f64;
+    let lost = limits.lost_time * phases;
+    let optimal_cycle = (1.5 * lost + 5.0) / (1.0 - total);
+    let cycle = optimal_cycle.clamp(limits.min_cycle, limits.max_cycle.max(limits.min_cycle));
+    // Phases whose share falls short of the minimum green get the minimum,
+    // taken from the others so the cycle stays as chosen
+    let mut greens: Vec<Option<f64>> = vec![None; critical.len()];
+    loop {
+        let open: Vec<usize> = (0..greens.len()).filter(|p| greens[*p].is_none()).collect();
+        let given: f64 = greens.iter().flatten().sum();
+        let count = open.len() as f64;
+        let effective = (cycle - given - limits.lost_time * count).max(0.0);
+        let ratios: f64 = open.iter().map(|p| critical[*p]).sum();
+        let share = |p: usize| match ratios > 0.0 {
+            true => critical[p] / ratios,
+            false => 1.0 / count,
+        };
+        let short: Vec<usize> = open
+            .iter()
+            .copied()
+            .filter(|p| effective * share(*p) + limits.lost_time < limits.min_green)
+            .collect();
+        if short.is_empty() {
+            for p in open {
+                greens[p] = Some(effective * share(p) + limits.lost_time);
+            }
+            break;
+        }
+        for p in short {
+            greens[p] = Some(limits.min_green);
+        }
+    }
+    let mut timed = plan.clone();
+    for (phase, green) in timed.phases.iter_mut().zip(greens.into_iter().flatten()) {
+        phase.green = ((green * FRAMES_PER_SECOND as f64).round() as u32).max(1);
+    }
+    Ok(Timing {
+        critical,
+        total,
+        optimal_cycle,
+        plan: timed,
+    })
+}
+
+fn rate(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::Phase;
+    use crate::vehicle::Direction;
+
+    // Two phases, north-south then east-west, all turns together
+    fn two_phase() -> SignalPlan {
+        let phase = |approaches: [Direction; 2]| Phase {
+            movements: Movement::all()
+                .into_iter()
+                .filter(|m| approaches.contains(&m.approach))
+                .collect(),
+            green: 1,
+        };
+        SignalPlan {
+            phases: vec![
+                phase([Direction::North, Direction::South]),
+                phase([Direction::East, Direction::West]),
+            ],
+        }
+    }
+
+    fn flows(volumes: &[(Direction, f64)]) -> Flows {
+        let mut flows = Flows::from_demand([0.0; 4], 1800.0);
+        for (approach, volume) in volumes {
+            let m = Movement {
+                approach: *approach,
+                turn: Turn::Straight,
+            };
+            flows.volume[m.index()] = *volume;
+        }
+        flows
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            lost_time: 4.0,
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn worked_example() {
+        // y = 540/1800 = 0.3 and 360/1800 = 0.2 for the two phases, so
+        // Y = 0.5 and with L = 2 x 4 s, C = (1.5 x 8 + 5) / (1 - 0.5) = 34 s.
+        // The effective green of 26 s splits 0.6 : 0.4 into 15.6 and 10.4 s,
+        // each plus its 4 s of lost time.
+        let flows = flows(&[
+            (Direction::North, 540.0),
+            (Direction::South, 360.0),
+            (Direction::East, 360.0),
+        ]);
+        let timing = optimise(&two_phase(), &flows, &limits()).unwrap();
+        assert!((timing.critical[0] - 0.3).abs() < 1e-9);
+        assert!((timing.critical[1] - 0.2).abs() < 1e-9);
+        assert!((timing.total - 0.5).abs() < 1e-9);
+        assert!((timing.optimal_cycle - 34.0).abs() < 1e-9);
+        let greens: Vec<u32> = timing.plan.phases.iter().map(|p| p.green).collect();
+        assert_eq!(greens, [1176, 864]); // 19.6 s and 14.4 s
+        assert_eq!(timing.cycle(), 34.0);
+    }
+
+    #[test]
+    fn cycle_is_clamped_to_the_limits() {
+        let busy = flows(&[(Direction::North, 540.0), (Direction::East, 360.0)]);
+        let capped = Limits {
+            max_cycle: 30.0,
+            ..limits()
+        };
+        let timing = optimise(&two_phase(), &busy, &capped).unwrap();
+        assert!((timing.optimal_cycle - 34.0).abs() < 1e-9);
+        assert_eq!(timing.cycle(), 30.0);
+
+        // Light demand gives a short optimum, raised to the minimum
+        let light = optimise(&two_phase(), &flows(&[(Direction::North, 90.0)]), &limits()).unwrap();
+        assert!(light.optimal_cycle < 30.0);
+        assert_eq!(light.cycle(), 30.0);
+    }
+
+    #[test]
+    fn demand_at_capacity_is_refused() {
+        // Y = 0.6 + 0.4 = 1: no cycle is long enough
+        let flows = flows(&[(Direction::North, 1080.0), (Direction::East, 720.0)]);
+        assert!(optimise(&two_phase(), &flows, &limits()).is_err());
+    }
+}